@@ -0,0 +1,182 @@
+use std::time::Duration;
+
+use lazy_static::lazy_static;
+use reqwest::Client;
+
+use crate::{
+    cache::{self, Cache},
+    config::{ImageLimits, CONFIG},
+};
+
+const DISCORD_CDN_HOSTS: [&str; 2] = ["https://cdn.discordapp.com", "https://media.discordapp.net"];
+
+lazy_static! {
+    static ref FETCHER: Fetcher = Fetcher::new(CONFIG.cdn_base_url.clone(), cache::configured());
+}
+
+#[derive(Debug)]
+pub enum FetchError {
+    Request(reqwest::Error),
+    TooLarge,
+}
+
+impl From<reqwest::Error> for FetchError {
+    fn from(error: reqwest::Error) -> Self {
+        FetchError::Request(error)
+    }
+}
+
+fn discord_cdn_path(url: &str) -> Option<&str> {
+    DISCORD_CDN_HOSTS
+        .iter()
+        .find_map(|host| url.strip_prefix(host))
+}
+
+/// Downloads `url`, giving up once the body grows past the limit instead of
+/// buffering whatever the server sends.
+async fn download(client: &Client, url: &str, limits: &ImageLimits) -> Result<Vec<u8>, FetchError> {
+    let mut response = client
+        .get(url)
+        .timeout(Duration::from_secs(limits.timeout_secs))
+        .send()
+        .await?
+        .error_for_status()?;
+
+    if response
+        .content_length()
+        .is_some_and(|length| length > limits.max_download_bytes)
+    {
+        return Err(FetchError::TooLarge);
+    }
+
+    let mut data = Vec::new();
+
+    while let Some(chunk) = response.chunk().await? {
+        data.extend_from_slice(&chunk);
+
+        if data.len() as u64 > limits.max_download_bytes {
+            return Err(FetchError::TooLarge);
+        }
+    }
+
+    Ok(data)
+}
+
+/// Where fetched assets come from and are kept. `cdn_base_url` is the one
+/// from `Config`; tests point it at their own server.
+pub struct Fetcher {
+    client: Client,
+    cdn_base_url: Option<String>,
+    cache: Option<Cache>,
+}
+
+impl Fetcher {
+    pub fn new(cdn_base_url: Option<String>, cache: Option<Cache>) -> Fetcher {
+        Fetcher {
+            client: Client::new(),
+            cdn_base_url,
+            cache,
+        }
+    }
+
+    /// Like `download`, but serves Discord CDN URLs from the cache when it
+    /// can. Those URLs are keyed by ID, so what they point to never changes.
+    pub async fn fetch(&self, url: &str, limits: &ImageLimits) -> Result<Vec<u8>, FetchError> {
+        let Some(cdn_path) = discord_cdn_path(url) else {
+            return download(&self.client, url, limits).await;
+        };
+
+        if let Some(cache) = &self.cache {
+            if let Some(data) = cache.load_url(url).await {
+                if data.len() as u64 <= limits.max_download_bytes {
+                    return Ok(data);
+                }
+            }
+        }
+
+        let data = match &self.cdn_base_url {
+            Some(cdn_base_url) => {
+                download(
+                    &self.client,
+                    &format!("{}{}", cdn_base_url, cdn_path),
+                    limits,
+                )
+                .await?
+            }
+            None => download(&self.client, url, limits).await?,
+        };
+
+        if let Some(cache) = &self.cache {
+            cache.store_url(url, data.clone()).await;
+        }
+
+        Ok(data)
+    }
+}
+
+/// Fetches `url` through the configured CDN stand-in and cache.
+pub async fn fetch(url: &str, limits: &ImageLimits) -> Result<Vec<u8>, FetchError> {
+    FETCHER.fetch(url, limits).await
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        env, fs,
+        io::{Read, Write},
+        net::TcpListener,
+        process,
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc,
+        },
+        thread,
+    };
+
+    use super::*;
+
+    /// Serves `body` to every request on a local port, counting requests.
+    fn serve(body: &'static [u8]) -> (String, Arc<AtomicUsize>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let requests = Arc::new(AtomicUsize::new(0));
+        let counter = requests.clone();
+
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let Ok(mut stream) = stream else {
+                    continue;
+                };
+
+                let mut request = [0; 1024];
+                let _ = stream.read(&mut request);
+
+                counter.fetch_add(1, Ordering::SeqCst);
+
+                let _ = write!(
+                    stream,
+                    "HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+                    body.len()
+                );
+                let _ = stream.write_all(body);
+            }
+        });
+
+        (format!("http://{}", address), requests)
+    }
+
+    #[tokio::test]
+    async fn second_fetch_is_served_from_cache() {
+        let (base_url, requests) = serve(b"fixture image");
+        let directory = env::temp_dir().join(format!("printer-cache-test-{}", process::id()));
+        let fetcher = Fetcher::new(Some(base_url), Some(Cache::new(&directory, 1024 * 1024)));
+        let limits = ImageLimits::default();
+        let url = "https://cdn.discordapp.com/emojis/1234.png";
+
+        assert_eq!(fetcher.fetch(url, &limits).await.unwrap(), b"fixture image");
+        assert_eq!(fetcher.fetch(url, &limits).await.unwrap(), b"fixture image");
+        assert_eq!(requests.load(Ordering::SeqCst), 1);
+
+        let _ = fs::remove_dir_all(directory);
+    }
+}
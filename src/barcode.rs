@@ -0,0 +1,265 @@
+use serde::{Deserialize, Serialize};
+
+const GS: u8 = 0x1D;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum Symbology {
+    UpcA,
+    Ean13,
+    Ean8,
+    Code39,
+    Itf,
+    Codabar,
+    Code93,
+    Code128,
+}
+
+/// Where the human readable digits go relative to the bars.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub enum HriPosition {
+    None,
+    Above,
+    #[default]
+    Below,
+    Both,
+}
+
+fn default_height() -> u8 {
+    80
+}
+
+fn default_width() -> u8 {
+    3
+}
+
+/// A barcode whose `data` has been checked against its symbology. `height`
+/// is in dots and `width` is the narrowest bar in dots, from 2 to 6.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(try_from = "BarcodeRepr", into = "BarcodeRepr")]
+pub struct Barcode {
+    pub symbology: Symbology,
+    pub data: String,
+    pub height: u8,
+    pub width: u8,
+    pub hri: HriPosition,
+}
+
+#[derive(Serialize, Deserialize)]
+struct BarcodeRepr {
+    symbology: Symbology,
+    data: String,
+    #[serde(default = "default_height")]
+    height: u8,
+    #[serde(default = "default_width")]
+    width: u8,
+    #[serde(default)]
+    hri: HriPosition,
+}
+
+impl TryFrom<BarcodeRepr> for Barcode {
+    type Error = String;
+
+    fn try_from(repr: BarcodeRepr) -> Result<Self, Self::Error> {
+        validate(repr.symbology, &repr.data)
+            .map_err(|error| format!("invalid {:?} barcode: {}", repr.symbology, error))?;
+
+        if repr.height == 0 {
+            return Err("barcode height must be at least 1".to_string());
+        }
+
+        if !(2..=6).contains(&repr.width) {
+            return Err("barcode width must be between 2 and 6".to_string());
+        }
+
+        Ok(Barcode {
+            symbology: repr.symbology,
+            data: repr.data,
+            height: repr.height,
+            width: repr.width,
+            hri: repr.hri,
+        })
+    }
+}
+
+impl From<Barcode> for BarcodeRepr {
+    fn from(barcode: Barcode) -> Self {
+        BarcodeRepr {
+            symbology: barcode.symbology,
+            data: barcode.data,
+            height: barcode.height,
+            width: barcode.width,
+            hri: barcode.hri,
+        }
+    }
+}
+
+/// The UPC/EAN check digit for `digits`, which excludes the check digit.
+fn check_digit(digits: &str) -> u32 {
+    let sum: u32 = digits
+        .chars()
+        .rev()
+        .filter_map(|c| c.to_digit(10))
+        .enumerate()
+        .map(|(index, digit)| match index % 2 {
+            0 => digit * 3,
+            _ => digit,
+        })
+        .sum();
+
+    (10 - sum % 10) % 10
+}
+
+/// UPC and EAN take their digits with or without the check digit. When it's
+/// given, it has to be right.
+fn validate_digits(data: &str, lengths: [usize; 2]) -> Result<(), String> {
+    if !data.chars().all(|c| c.is_ascii_digit()) {
+        return Err("only digits are allowed".to_string());
+    }
+
+    if data.len() == lengths[1] {
+        let (digits, check) = data.split_at(lengths[0]);
+        let expected = check_digit(digits);
+
+        if check.parse::<u32>() != Ok(expected) {
+            return Err(format!("check digit should be {}", expected));
+        }
+
+        return Ok(());
+    }
+
+    match data.len() == lengths[0] {
+        true => Ok(()),
+        false => Err(format!("takes {} or {} digits", lengths[0], lengths[1])),
+    }
+}
+
+fn validate_charset(data: &str, allowed: impl Fn(char) -> bool) -> Result<(), String> {
+    match data.chars().find(|c| !allowed(*c)) {
+        Some(c) => Err(format!("{:?} can't be encoded", c)),
+        None => Ok(()),
+    }
+}
+
+/// The ESC/POS barcode type and the bytes sent for `data`.
+fn encode(symbology: Symbology, data: &str) -> (u8, String) {
+    match symbology {
+        Symbology::UpcA => (65, data.to_string()),
+        Symbology::Ean13 => (67, data.to_string()),
+        Symbology::Ean8 => (68, data.to_string()),
+        Symbology::Code39 => (69, data.to_string()),
+        Symbology::Itf => (70, data.to_string()),
+        Symbology::Codabar => (71, data.to_string()),
+        Symbology::Code93 => (72, data.to_string()),
+        // Code set B covers printable ASCII; `{` starts a code set switch.
+        Symbology::Code128 => (73, format!("{{B{}", data.replace('{', "{{"))),
+    }
+}
+
+fn validate(symbology: Symbology, data: &str) -> Result<(), String> {
+    if data.is_empty() {
+        return Err("data is empty".to_string());
+    }
+
+    validate_symbology(symbology, data)?;
+
+    // The printer takes the length as a single byte.
+    match encode(symbology, data).1.len() <= 255 {
+        true => Ok(()),
+        false => Err("data is too long".to_string()),
+    }
+}
+
+fn validate_symbology(symbology: Symbology, data: &str) -> Result<(), String> {
+    match symbology {
+        Symbology::UpcA => validate_digits(data, [11, 12]),
+        Symbology::Ean13 => validate_digits(data, [12, 13]),
+        Symbology::Ean8 => validate_digits(data, [7, 8]),
+        // `*` is the start and stop character, so it can't be in the data.
+        Symbology::Code39 => validate_charset(data, |c| {
+            c.is_ascii_digit() || c.is_ascii_uppercase() || " $%+-./".contains(c)
+        }),
+        Symbology::Itf => {
+            validate_charset(data, |c| c.is_ascii_digit())?;
+
+            match data.len() % 2 {
+                0 => Ok(()),
+                _ => Err("takes an even number of digits".to_string()),
+            }
+        }
+        Symbology::Codabar => {
+            validate_charset(data, |c| c.is_ascii_digit() || "ABCD$+-./:".contains(c))?;
+
+            let is_stop = |c: Option<char>| c.is_some_and(|c| "ABCD".contains(c));
+
+            match data.len() >= 2 && is_stop(data.chars().next()) && is_stop(data.chars().last()) {
+                true => Ok(()),
+                false => Err("must start and end with one of A, B, C or D".to_string()),
+            }
+        }
+        Symbology::Code93 => validate_charset(data, |c| c.is_ascii()),
+        // Printed in code set B, which has no control characters.
+        Symbology::Code128 => validate_charset(data, |c| c == ' ' || c.is_ascii_graphic()),
+    }
+}
+
+impl Barcode {
+    /// The ESC/POS commands that print this barcode on its own line.
+    pub fn commands(&self) -> Vec<u8> {
+        let (kind, data) = encode(self.symbology, &self.data);
+
+        let hri = match self.hri {
+            HriPosition::None => 0,
+            HriPosition::Above => 1,
+            HriPosition::Below => 2,
+            HriPosition::Both => 3,
+        };
+
+        let mut commands = Vec::new();
+
+        commands.extend_from_slice(&[GS, b'h', self.height]);
+        commands.extend_from_slice(&[GS, b'w', self.width]);
+        commands.extend_from_slice(&[GS, b'H', hri]);
+        commands.extend_from_slice(&[GS, b'k', kind, data.len() as u8]);
+        commands.extend_from_slice(data.as_bytes());
+
+        commands
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(symbology: &str, data: &str) -> Result<Barcode, serde_json::Error> {
+        serde_json::from_value(serde_json::json!({ "symbology": symbology, "data": data }))
+    }
+
+    #[test]
+    fn code128_rejects_control_characters() {
+        assert!(parse("Code128", "tab\there").is_err());
+        assert!(parse("Code128", "bell\u{7}").is_err());
+        assert!(parse("Code128", "Hello {world} ~").is_ok());
+    }
+
+    #[test]
+    fn ean13_checks_the_check_digit() {
+        assert!(parse("Ean13", "400638133393").is_ok());
+        assert!(parse("Ean13", "4006381333931").is_ok());
+        assert!(parse("Ean13", "4006381333932").is_err());
+    }
+
+    #[test]
+    fn data_over_255_bytes_is_rejected() {
+        assert!(parse("Code39", &"A".repeat(255)).is_ok());
+        assert!(parse("Code39", &"A".repeat(256)).is_err());
+        assert!(parse("Itf", &"12".repeat(128)).is_err());
+        assert!(parse("Codabar", &format!("A{}A", "1".repeat(254))).is_err());
+        assert!(parse("Code128", &"{".repeat(127)).is_err());
+    }
+
+    #[test]
+    fn code39_rejects_its_start_character() {
+        assert!(parse("Code39", "ABC-123").is_ok());
+        assert!(parse("Code39", "A*B").is_err());
+    }
+}
@@ -0,0 +1,269 @@
+use std::{
+    fs::{self, File},
+    io,
+    path::PathBuf,
+    process,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex, PoisonError,
+    },
+    time::SystemTime,
+};
+
+use lazy_static::lazy_static;
+use sha2::{Digest, Sha256};
+
+use crate::config::CONFIG;
+
+/// The cache keeps three kinds of files: `Url` maps a URL to the hash of what
+/// it served, `Blob` holds downloaded bytes by their own hash, and `Raster`
+/// holds processed bitmaps by the hash of their source and settings.
+#[derive(Debug, Clone, Copy)]
+pub enum Entry {
+    Url,
+    Blob,
+    Raster,
+}
+
+impl Entry {
+    fn directory(self) -> &'static str {
+        match self {
+            Entry::Url => "urls",
+            Entry::Blob => "blobs",
+            Entry::Raster => "raster",
+        }
+    }
+}
+
+static NEXT_WRITE: AtomicUsize = AtomicUsize::new(0);
+
+pub fn key(parts: &[&[u8]]) -> String {
+    let mut hasher = Sha256::new();
+
+    for part in parts {
+        hasher.update((part.len() as u64).to_le_bytes());
+        hasher.update(part);
+    }
+
+    format!("{:x}", hasher.finalize())
+}
+
+/// A cache directory with a size budget. The configured one is shared by
+/// every fetch; tests make their own in a temporary directory. `size` is
+/// counted on the first write and kept up to date after that, so the files
+/// are only listed again once the cache goes over budget.
+#[derive(Debug, Clone)]
+pub struct Cache {
+    directory: PathBuf,
+    max_bytes: u64,
+    size: Arc<Mutex<Option<u64>>>,
+}
+
+lazy_static! {
+    static ref CACHE: Option<Cache> = CONFIG
+        .cache
+        .enabled
+        .then(|| Cache::new(&CONFIG.cache.directory, CONFIG.cache.max_bytes));
+}
+
+/// The cache set up in `config.json`, unless it's turned off.
+pub fn configured() -> Option<Cache> {
+    CACHE.clone()
+}
+
+impl Cache {
+    pub fn new(directory: impl Into<PathBuf>, max_bytes: u64) -> Cache {
+        Cache {
+            directory: directory.into(),
+            max_bytes,
+            size: Arc::default(),
+        }
+    }
+
+    fn path(&self, entry: Entry, key: &str) -> PathBuf {
+        self.directory.join(entry.directory()).join(key)
+    }
+
+    /// Reading an entry bumps its modification time, which eviction treats
+    /// as the last time it was used.
+    fn read(&self, entry: Entry, key: &str) -> Option<Vec<u8>> {
+        let path = self.path(entry, key);
+        let data = fs::read(&path).ok()?;
+
+        if let Ok(file) = File::options().write(true).open(&path) {
+            let _ = file.set_modified(SystemTime::now());
+        }
+
+        Some(data)
+    }
+
+    /// Writes to a temporary file first and renames it into place, so
+    /// readers and a crash halfway through never leave a partial entry.
+    fn write(&self, entry: Entry, key: &str, data: &[u8]) -> io::Result<()> {
+        let path = self.path(entry, key);
+
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        let replaced_size = fs::metadata(&path).map_or(0, |metadata| metadata.len());
+        let temporary_path = path.with_extension(format!(
+            "{}-{}.tmp",
+            process::id(),
+            NEXT_WRITE.fetch_add(1, Ordering::Relaxed)
+        ));
+
+        fs::write(&temporary_path, data)?;
+
+        if let Err(error) = fs::rename(&temporary_path, &path) {
+            let _ = fs::remove_file(&temporary_path);
+            return Err(error);
+        }
+
+        let mut size = self.size.lock().unwrap_or_else(PoisonError::into_inner);
+        let total_size = match *size {
+            Some(total_size) => (total_size + data.len() as u64).saturating_sub(replaced_size),
+            None => self.files().iter().map(|(_, size, _)| size).sum(),
+        };
+
+        *size = Some(match total_size > self.max_bytes {
+            true => self.evict(),
+            false => total_size,
+        });
+
+        Ok(())
+    }
+
+    /// Every cached file with its modification time and size.
+    fn files(&self) -> Vec<(SystemTime, u64, PathBuf)> {
+        let mut files = Vec::new();
+
+        for entry in [Entry::Url, Entry::Blob, Entry::Raster] {
+            let Ok(directory) = fs::read_dir(self.directory.join(entry.directory())) else {
+                continue;
+            };
+
+            for file in directory.flatten() {
+                let Ok(metadata) = file.metadata() else {
+                    continue;
+                };
+
+                files.push((
+                    metadata.modified().unwrap_or(SystemTime::UNIX_EPOCH),
+                    metadata.len(),
+                    file.path(),
+                ));
+            }
+        }
+
+        files
+    }
+
+    /// Deletes the least recently used files until the cache fits its budget,
+    /// and returns what's left. The files are counted again, since other
+    /// processes may share the directory.
+    fn evict(&self) -> u64 {
+        let mut files = self.files();
+        let mut total_size = files.iter().map(|(_, size, _)| size).sum();
+
+        files.sort_by_key(|(modified, _, _)| *modified);
+
+        for (_, size, path) in files {
+            if total_size <= self.max_bytes {
+                break;
+            }
+
+            // Another job may have evicted it already.
+            let _ = fs::remove_file(path);
+            total_size -= size;
+        }
+
+        total_size
+    }
+
+    pub async fn load(&self, entry: Entry, key: String) -> Option<Vec<u8>> {
+        let cache = self.clone();
+
+        tokio::task::spawn_blocking(move || cache.read(entry, &key))
+            .await
+            .ok()
+            .flatten()
+    }
+
+    pub async fn store(&self, entry: Entry, key: String, data: Vec<u8>) {
+        let cache = self.clone();
+
+        let _ = tokio::task::spawn_blocking(move || cache.write(entry, &key, &data)).await;
+    }
+
+    /// Blobs are named by the hash of their contents, so one that doesn't
+    /// match its name is treated as missing.
+    pub async fn load_url(&self, url: &str) -> Option<Vec<u8>> {
+        let content_key = self.load(Entry::Url, key(&[url.as_bytes()])).await?;
+        let content_key = String::from_utf8(content_key).ok()?;
+        let data = self.load(Entry::Blob, content_key.clone()).await?;
+
+        (key(&[&data]) == content_key).then_some(data)
+    }
+
+    pub async fn store_url(&self, url: &str, data: Vec<u8>) {
+        let content_key = key(&[&data]);
+
+        self.store(Entry::Blob, content_key.clone(), data).await;
+        self.store(Entry::Url, key(&[url.as_bytes()]), content_key.into_bytes())
+            .await;
+    }
+}
+
+pub async fn load(entry: Entry, key: String) -> Option<Vec<u8>> {
+    CACHE.as_ref()?.load(entry, key).await
+}
+
+pub async fn store(entry: Entry, key: String, data: Vec<u8>) {
+    if let Some(cache) = CACHE.as_ref() {
+        cache.store(entry, key, data).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn truncated_blob_is_a_miss() {
+        let directory = env::temp_dir().join(format!("printer-cache-blob-test-{}", process::id()));
+        let cache = Cache::new(&directory, 1024 * 1024);
+        let url = "https://cdn.discordapp.com/emojis/1.png";
+        let data = b"complete image".to_vec();
+
+        cache.store_url(url, data.clone()).await;
+        assert_eq!(cache.load_url(url).await, Some(data.clone()));
+
+        fs::write(cache.path(Entry::Blob, &key(&[&data])), b"compl").unwrap();
+        assert_eq!(cache.load_url(url).await, None);
+
+        let _ = fs::remove_dir_all(directory);
+    }
+
+    #[test]
+    fn writes_past_the_budget_evict_the_oldest() {
+        let directory = env::temp_dir().join(format!("printer-cache-evict-test-{}", process::id()));
+        let cache = Cache::new(&directory, 10);
+
+        cache.write(Entry::Raster, "old", b"123456").unwrap();
+        File::options()
+            .write(true)
+            .open(cache.path(Entry::Raster, "old"))
+            .and_then(|file| file.set_modified(SystemTime::UNIX_EPOCH))
+            .unwrap();
+        cache.write(Entry::Raster, "new", b"123456").unwrap();
+
+        assert_eq!(cache.read(Entry::Raster, "old"), None);
+        assert_eq!(cache.read(Entry::Raster, "new"), Some(b"123456".to_vec()));
+        assert_eq!(*cache.size.lock().unwrap(), Some(6));
+
+        let _ = fs::remove_dir_all(directory);
+    }
+}
@@ -0,0 +1,257 @@
+use std::{collections::HashMap, fs};
+
+use lazy_static::lazy_static;
+use serde::Deserialize;
+use serenity::all::ChannelId;
+
+use crate::{
+    imaging::{AnimationMode, ImageSettings},
+    JobSource, QrCorrection, TextMode,
+};
+
+const CONFIG_PATH: &str = "config.json";
+
+/// Settings that can be chosen per Discord channel. Channels without an entry
+/// in `config.json` use `default`.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct ChannelConfig {
+    pub text_mode: TextMode,
+    pub image: ImageSettings,
+    pub qr_codes: QrCodeConfig,
+    pub code: CodeConfig,
+    pub spoilers: SpoilerMode,
+    pub stickers: StickerConfig,
+    pub text_attachments: TextAttachmentConfig,
+    pub collage: CollageConfig,
+    pub avatars: AvatarConfig,
+}
+
+/// Prints the author's avatar above the header, `width` dots wide.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct AvatarConfig {
+    pub enabled: bool,
+    pub width: u32,
+}
+
+impl Default for AvatarConfig {
+    fn default() -> Self {
+        AvatarConfig {
+            enabled: false,
+            width: 96,
+        }
+    }
+}
+
+/// Combines the image attachments of a message into one grid, `columns`
+/// wide with `gap` dots between images.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct CollageConfig {
+    pub enabled: bool,
+    pub columns: u32,
+    pub gap: u32,
+}
+
+impl Default for CollageConfig {
+    fn default() -> Self {
+        CollageConfig {
+            enabled: false,
+            columns: 2,
+            gap: 8,
+        }
+    }
+}
+
+/// Text files up to `max_bytes` are printed as code blocks, showing at most
+/// `max_lines` lines.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct TextAttachmentConfig {
+    pub enabled: bool,
+    pub max_bytes: u64,
+    pub max_lines: usize,
+}
+
+impl Default for TextAttachmentConfig {
+    fn default() -> Self {
+        TextAttachmentConfig {
+            enabled: true,
+            max_bytes: 16 * 1024,
+            max_lines: 100,
+        }
+    }
+}
+
+/// Which frames of animated stickers to print. The first frame of many of
+/// them is still blank, so this defaults to the middle one.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct StickerConfig {
+    pub animation: AnimationMode,
+}
+
+impl Default for StickerConfig {
+    fn default() -> Self {
+        StickerConfig {
+            animation: AnimationMode::Middle,
+        }
+    }
+}
+
+/// `Fold` prints spoilers at the end of the receipt, below a line marking
+/// where to fold it over.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize)]
+pub enum SpoilerMode {
+    #[default]
+    Redact,
+    UpsideDown,
+    Fold,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct CodeConfig {
+    pub highlight: bool,
+    pub line_numbers: bool,
+}
+
+impl Default for CodeConfig {
+    fn default() -> Self {
+        CodeConfig {
+            highlight: true,
+            line_numbers: false,
+        }
+    }
+}
+
+/// Which URLs get a QR code printed at the bottom of the message. `links`
+/// also turns the footnotes of masked links into QR codes.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct QrCodeConfig {
+    pub links: bool,
+    pub attachments: bool,
+    pub jump_link: bool,
+    pub size: u8,
+    pub correction: QrCorrection,
+}
+
+impl Default for QrCodeConfig {
+    fn default() -> Self {
+        QrCodeConfig {
+            links: false,
+            attachments: false,
+            jump_link: false,
+            size: 4,
+            correction: QrCorrection::M,
+        }
+    }
+}
+
+/// What to do with an image that would print taller than `max_height_mm`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize)]
+pub enum OverflowMode {
+    Crop,
+    #[default]
+    Downscale,
+    Notice,
+}
+
+/// Caps on what a single image may cost. Downloads and decodes over the limit
+/// are replaced with a notice; tall images follow `overflow`.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct ImageLimits {
+    pub max_download_bytes: u64,
+    pub timeout_secs: u64,
+    pub max_decode_width: u32,
+    pub max_decode_height: u32,
+    pub max_height_mm: u32,
+    pub overflow: OverflowMode,
+}
+
+impl Default for ImageLimits {
+    fn default() -> Self {
+        ImageLimits {
+            max_download_bytes: 8 * 1024 * 1024,
+            timeout_secs: 10,
+            max_decode_width: 4096,
+            max_decode_height: 4096,
+            max_height_mm: 150,
+            overflow: OverflowMode::Downscale,
+        }
+    }
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct SourceLimits {
+    pub discord: ImageLimits,
+    pub api: ImageLimits,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+pub struct CacheConfig {
+    pub enabled: bool,
+    pub directory: String,
+    pub max_bytes: u64,
+}
+
+impl Default for CacheConfig {
+    fn default() -> Self {
+        CacheConfig {
+            enabled: true,
+            directory: "cache".to_string(),
+            max_bytes: 256 * 1024 * 1024,
+        }
+    }
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    pub default: ChannelConfig,
+    pub channels: HashMap<u64, ChannelConfig>,
+    pub limits: SourceLimits,
+    pub cache: CacheConfig,
+    /// Replaces the Discord CDN hosts in every fetched URL, so a local HTTP
+    /// server can stand in for them.
+    pub cdn_base_url: Option<String>,
+}
+
+impl Config {
+    fn load() -> Config {
+        let Ok(contents) = fs::read_to_string(CONFIG_PATH) else {
+            return Config::default();
+        };
+
+        match serde_json::from_str(&contents) {
+            Ok(config) => config,
+            Err(error) => {
+                println!("invalid {}: {:?}", CONFIG_PATH, error);
+
+                Config::default()
+            }
+        }
+    }
+
+    pub fn channel(&self, channel_id: ChannelId) -> &ChannelConfig {
+        self.channels
+            .get(&channel_id.get())
+            .unwrap_or(&self.default)
+    }
+
+    pub fn limits(&self, source: JobSource) -> &ImageLimits {
+        match source {
+            JobSource::Discord => &self.limits.discord,
+            JobSource::Api => &self.limits.api,
+        }
+    }
+}
+
+lazy_static! {
+    pub static ref CONFIG: Config = Config::load();
+}
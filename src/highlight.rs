@@ -0,0 +1,326 @@
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TokenKind {
+    Plain,
+    Keyword,
+    String,
+    Comment,
+}
+
+pub struct Language {
+    keywords: &'static [&'static str],
+    line_comments: &'static [&'static str],
+    block_comment: Option<(&'static str, &'static str)>,
+    quotes: &'static [char],
+}
+
+const RUST: Language = Language {
+    keywords: &[
+        "as", "async", "await", "break", "const", "continue", "crate", "dyn", "else", "enum",
+        "extern", "false", "fn", "for", "if", "impl", "in", "let", "loop", "match", "mod", "move",
+        "mut", "pub", "ref", "return", "self", "Self", "static", "struct", "super", "trait",
+        "true", "type", "unsafe", "use", "where", "while",
+    ],
+    line_comments: &["//"],
+    block_comment: Some(("/*", "*/")),
+    // Single quotes are left out, since they also start lifetimes.
+    quotes: &['"'],
+};
+
+const PYTHON: Language = Language {
+    keywords: &[
+        "and", "as", "assert", "async", "await", "break", "class", "continue", "def", "del",
+        "elif", "else", "except", "False", "finally", "for", "from", "global", "if", "import",
+        "in", "is", "lambda", "None", "nonlocal", "not", "or", "pass", "raise", "return", "True",
+        "try", "while", "with", "yield",
+    ],
+    line_comments: &["#"],
+    block_comment: None,
+    quotes: &['"', '\''],
+};
+
+const JAVASCRIPT: Language = Language {
+    keywords: &[
+        "async",
+        "await",
+        "break",
+        "case",
+        "catch",
+        "class",
+        "const",
+        "continue",
+        "default",
+        "delete",
+        "do",
+        "else",
+        "export",
+        "extends",
+        "false",
+        "finally",
+        "for",
+        "from",
+        "function",
+        "if",
+        "import",
+        "in",
+        "instanceof",
+        "let",
+        "new",
+        "null",
+        "of",
+        "return",
+        "static",
+        "super",
+        "switch",
+        "this",
+        "throw",
+        "true",
+        "try",
+        "typeof",
+        "undefined",
+        "var",
+        "void",
+        "while",
+        "yield",
+    ],
+    line_comments: &["//"],
+    block_comment: Some(("/*", "*/")),
+    quotes: &['"', '\'', '`'],
+};
+
+const JSON: Language = Language {
+    keywords: &["true", "false", "null"],
+    line_comments: &[],
+    block_comment: None,
+    quotes: &['"'],
+};
+
+const SHELL: Language = Language {
+    keywords: &[
+        "case", "do", "done", "elif", "else", "esac", "export", "fi", "for", "function", "if",
+        "in", "local", "return", "then", "until", "while",
+    ],
+    line_comments: &["#"],
+    block_comment: None,
+    quotes: &['"', '\''],
+};
+
+/// Looks up a language by the tag after a code fence.
+pub fn language(tag: &str) -> Option<&'static Language> {
+    match tag.to_lowercase().as_str() {
+        "rust" | "rs" => Some(&RUST),
+        "python" | "py" => Some(&PYTHON),
+        "javascript" | "js" | "jsx" | "typescript" | "ts" | "tsx" => Some(&JAVASCRIPT),
+        "json" => Some(&JSON),
+        "shell" | "sh" | "bash" | "zsh" | "console" => Some(&SHELL),
+        _ => None,
+    }
+}
+
+fn push_token(tokens: &mut Vec<(TokenKind, String)>, kind: TokenKind, text: &str) {
+    match tokens.last_mut() {
+        Some((last_kind, last_text)) if *last_kind == kind => last_text.push_str(text),
+        _ => tokens.push((kind, text.to_string())),
+    }
+}
+
+/// Where the string starting at `start` ends, skipping escaped quotes. An
+/// unterminated string runs to the end of the line.
+fn string_end(line: &str, start: usize, quote: char) -> usize {
+    let mut escaped = false;
+
+    for (offset, c) in line[start + quote.len_utf8()..].char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' => escaped = true,
+            c if c == quote => return start + quote.len_utf8() + offset + c.len_utf8(),
+            _ => {}
+        }
+    }
+
+    line.len()
+}
+
+/// Splits `code` into lines of tokens. Block comments carry over between
+/// lines; strings don't.
+pub fn highlight(code: &str, language: &Language) -> Vec<Vec<(TokenKind, String)>> {
+    let mut lines = Vec::new();
+    let mut in_block_comment = false;
+
+    for line in code.split('\n') {
+        let mut tokens = Vec::new();
+        let mut position = 0;
+
+        while position < line.len() {
+            let rest = &line[position..];
+
+            if in_block_comment {
+                let end = match language.block_comment {
+                    Some((_, close)) => rest.find(close).map(|index| index + close.len()),
+                    None => None,
+                };
+
+                let end = match end {
+                    Some(end) => {
+                        in_block_comment = false;
+                        end
+                    }
+                    None => rest.len(),
+                };
+
+                push_token(&mut tokens, TokenKind::Comment, &rest[..end]);
+                position += end;
+                continue;
+            }
+
+            let after_space = line[..position]
+                .chars()
+                .next_back()
+                .is_none_or(char::is_whitespace);
+
+            if language
+                .line_comments
+                .iter()
+                .any(|comment| rest.starts_with(comment) && (*comment != "#" || after_space))
+            {
+                push_token(&mut tokens, TokenKind::Comment, rest);
+                break;
+            }
+
+            if let Some((open, _)) = language.block_comment {
+                if rest.starts_with(open) {
+                    in_block_comment = true;
+                    push_token(&mut tokens, TokenKind::Comment, open);
+                    position += open.len();
+                    continue;
+                }
+            }
+
+            let Some(c) = rest.chars().next() else {
+                break;
+            };
+
+            if language.quotes.contains(&c) {
+                let end = string_end(line, position, c);
+
+                push_token(&mut tokens, TokenKind::String, &line[position..end]);
+                position = end;
+                continue;
+            }
+
+            if c.is_alphanumeric() || c == '_' {
+                let end = rest
+                    .find(|c: char| !(c.is_alphanumeric() || c == '_'))
+                    .unwrap_or(rest.len());
+                let word = &rest[..end];
+
+                let kind = match language.keywords.contains(&word) {
+                    true => TokenKind::Keyword,
+                    false => TokenKind::Plain,
+                };
+
+                push_token(&mut tokens, kind, word);
+                position += end;
+                continue;
+            }
+
+            push_token(&mut tokens, TokenKind::Plain, &rest[..c.len_utf8()]);
+            position += c.len_utf8();
+        }
+
+        lines.push(tokens);
+    }
+
+    lines
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tokens(code: &str, tag: &str) -> Vec<Vec<(TokenKind, String)>> {
+        highlight(code, language(tag).unwrap())
+    }
+
+    fn of_kind(code: &str, tag: &str, kind: TokenKind) -> Vec<String> {
+        tokens(code, tag)
+            .into_iter()
+            .flatten()
+            .filter(|(token_kind, _)| *token_kind == kind)
+            .map(|(_, text)| text)
+            .collect()
+    }
+
+    #[test]
+    fn languages_are_found_by_tag() {
+        assert!(language("RS").is_some());
+        assert!(language("tsx").is_some());
+        assert!(language("cobol").is_none());
+    }
+
+    #[test]
+    fn rust_strings_keep_escaped_quotes() {
+        let code = r#"let s = "a \" b"; // done"#;
+
+        assert_eq!(of_kind(code, "rust", TokenKind::Keyword), ["let"]);
+        assert_eq!(of_kind(code, "rust", TokenKind::String), [r#""a \" b""#]);
+        assert_eq!(of_kind(code, "rust", TokenKind::Comment), ["// done"]);
+    }
+
+    #[test]
+    fn rust_block_comments_span_lines() {
+        assert_eq!(
+            tokens("a /* one\ntwo */ fn", "rust"),
+            [
+                vec![
+                    (TokenKind::Plain, "a ".to_string()),
+                    (TokenKind::Comment, "/* one".to_string()),
+                ],
+                vec![
+                    (TokenKind::Comment, "two */".to_string()),
+                    (TokenKind::Plain, " ".to_string()),
+                    (TokenKind::Keyword, "fn".to_string()),
+                ],
+            ]
+        );
+    }
+
+    #[test]
+    fn python_keywords_strings_and_comments() {
+        let code = r"def f(): return 'it\'s' # note";
+
+        assert_eq!(of_kind(code, "py", TokenKind::Keyword), ["def", "return"]);
+        assert_eq!(of_kind(code, "py", TokenKind::String), [r"'it\'s'"]);
+        assert_eq!(of_kind(code, "py", TokenKind::Comment), ["# note"]);
+    }
+
+    #[test]
+    fn javascript_template_strings() {
+        let code = "const x = `a ${b}` /* c */";
+
+        assert_eq!(of_kind(code, "js", TokenKind::Keyword), ["const"]);
+        assert_eq!(of_kind(code, "js", TokenKind::String), ["`a ${b}`"]);
+        assert_eq!(of_kind(code, "js", TokenKind::Comment), ["/* c */"]);
+    }
+
+    #[test]
+    fn json_literals() {
+        let code = r#"{"a": true, "b": null}"#;
+
+        assert_eq!(of_kind(code, "json", TokenKind::Keyword), ["true", "null"]);
+        assert_eq!(
+            of_kind(code, "json", TokenKind::String),
+            [r#""a""#, r#""b""#]
+        );
+    }
+
+    #[test]
+    fn shell_comments_start_after_whitespace() {
+        let code = "echo $# x#y # real";
+
+        assert_eq!(of_kind(code, "sh", TokenKind::Comment), ["# real"]);
+        assert_eq!(
+            of_kind("# whole line", "sh", TokenKind::Comment),
+            ["# whole line"]
+        );
+    }
+}
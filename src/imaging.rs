@@ -0,0 +1,715 @@
+use std::{fmt, io::Cursor};
+
+use image::{
+    codecs::{gif::GifDecoder, png::PngDecoder, webp::WebPDecoder},
+    error::{LimitError, LimitErrorKind, ParameterError, ParameterErrorKind},
+    imageops::{self, FilterType},
+    AnimationDecoder, DynamicImage, GrayImage, ImageDecoder, ImageError, ImageFormat, ImageReader,
+    ImageResult, Limits, Luma, Rgba, RgbaImage,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    config::{ImageLimits, OverflowMode},
+    lottie::Animation,
+    DOTS_PER_LINE, DOTS_PER_MM,
+};
+
+#[derive(Debug)]
+pub enum ProcessError {
+    Image(ImageError),
+    OverLimit,
+}
+
+impl From<ImageError> for ProcessError {
+    fn from(error: ImageError) -> Self {
+        match error {
+            ImageError::Limits(_) => ProcessError::OverLimit,
+            error => ProcessError::Image(error),
+        }
+    }
+}
+
+/// A processed PNG, ready to hand to the printer as is.
+#[derive(Clone)]
+pub struct Bitmap(pub Vec<u8>);
+
+impl fmt::Debug for Bitmap {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Bitmap({} bytes)", self.0.len())
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub enum DitherMode {
+    #[default]
+    FloydSteinberg,
+    Atkinson,
+    Threshold,
+}
+
+/// Which frames of an animated image get printed. `Flipbook` prints that
+/// many evenly spaced frames stacked down the receipt, up to 16.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub enum AnimationMode {
+    #[default]
+    First,
+    Middle,
+    Flipbook(u32),
+}
+
+const MAX_FRAMES: usize = 1000;
+const MAX_FLIPBOOK_FRAMES: usize = 16;
+const FLIPBOOK_GAP: u32 = 8;
+
+/// How images are turned into dots. `width` defaults to shrinking anything
+/// wider than the paper; `gamma` above 1 darkens midtones and `contrast`
+/// above 1 pushes values away from middle gray. `upside_down` turns the image
+/// to go with upside down text.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ImageSettings {
+    pub width: Option<u32>,
+    pub dither: DitherMode,
+    pub gamma: f32,
+    pub contrast: f32,
+    pub threshold: u8,
+    pub animation: AnimationMode,
+    pub upside_down: bool,
+}
+
+impl Default for ImageSettings {
+    fn default() -> Self {
+        ImageSettings {
+            width: None,
+            dither: DitherMode::FloydSteinberg,
+            gamma: 1.0,
+            contrast: 1.0,
+            threshold: 128,
+            animation: AnimationMode::First,
+            upside_down: false,
+        }
+    }
+}
+
+/// Per-image overrides of the job's `ImageSettings`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ImageOverrides {
+    pub width: Option<u32>,
+    pub dither: Option<DitherMode>,
+    pub gamma: Option<f32>,
+    pub contrast: Option<f32>,
+    pub threshold: Option<u8>,
+    pub animation: Option<AnimationMode>,
+    pub upside_down: Option<bool>,
+}
+
+impl ImageSettings {
+    pub fn with(&self, overrides: &ImageOverrides) -> ImageSettings {
+        ImageSettings {
+            width: overrides.width.or(self.width),
+            dither: overrides.dither.unwrap_or(self.dither),
+            gamma: overrides.gamma.unwrap_or(self.gamma),
+            contrast: overrides.contrast.unwrap_or(self.contrast),
+            threshold: overrides.threshold.unwrap_or(self.threshold),
+            animation: overrides.animation.unwrap_or(self.animation),
+            upside_down: overrides.upside_down.unwrap_or(self.upside_down),
+        }
+    }
+}
+
+/// Flattens transparency onto white paper and converts to grayscale.
+fn to_gray(image: &DynamicImage) -> GrayImage {
+    let rgba = image.to_rgba8();
+
+    GrayImage::from_fn(rgba.width(), rgba.height(), |x, y| {
+        let [r, g, b, a] = rgba.get_pixel(x, y).0;
+        let luma = 0.299 * r as f32 + 0.587 * g as f32 + 0.114 * b as f32;
+        let alpha = a as f32 / 255.0;
+
+        Luma([(luma * alpha + 255.0 * (1.0 - alpha)).round() as u8])
+    })
+}
+
+/// The width `settings` asks for, or else the image's own, within the paper.
+fn target_width(width: u32, settings: &ImageSettings) -> u32 {
+    settings.width.unwrap_or(width).clamp(1, DOTS_PER_LINE)
+}
+
+/// Scales the image to the paper, keeping it within `max_height_mm` the way
+/// `overflow` says. The height is settled before resizing, so a thin, tall
+/// image is never blown up past the limit first.
+fn scale(
+    image: DynamicImage,
+    settings: &ImageSettings,
+    limits: &ImageLimits,
+) -> Result<DynamicImage, ProcessError> {
+    let max_height = (limits.max_height_mm * DOTS_PER_MM).max(1);
+    let target_width = target_width(image.width(), settings);
+    let target_height =
+        ((image.height() as u64 * target_width as u64) / image.width().max(1) as u64).max(1);
+
+    if target_height <= max_height as u64 {
+        return Ok(match target_width == image.width() {
+            true => image,
+            false => image.resize_exact(target_width, target_height as u32, FilterType::Triangle),
+        });
+    }
+
+    match limits.overflow {
+        OverflowMode::Crop => {
+            let source_height = (max_height as u64 * image.width() as u64 / target_width as u64)
+                .clamp(1, image.height() as u64);
+            let image = image.crop_imm(0, 0, image.width(), source_height as u32);
+
+            Ok(image.resize_exact(target_width, max_height, FilterType::Triangle))
+        }
+        OverflowMode::Downscale => {
+            let width = (target_width as u64 * max_height as u64 / target_height).max(1);
+
+            Ok(image.resize_exact(width as u32, max_height, FilterType::Triangle))
+        }
+        OverflowMode::Notice => Err(ProcessError::OverLimit),
+    }
+}
+
+fn adjust(gray: &GrayImage, settings: &ImageSettings) -> Vec<f32> {
+    gray.pixels()
+        .map(|pixel| {
+            let value = (pixel.0[0] as f32 / 255.0).powf(settings.gamma.max(0.01));
+
+            ((value - 0.5) * settings.contrast + 0.5).clamp(0.0, 1.0)
+        })
+        .collect()
+}
+
+/// Diffuses each pixel's quantization error to its neighbours, given as
+/// (dx, dy, weight).
+fn diffuse(values: &mut [f32], width: usize, threshold: f32, kernel: &[(isize, isize, f32)]) {
+    let height = values.len() / width.max(1);
+
+    for y in 0..height {
+        for x in 0..width {
+            let index = y * width + x;
+            let old = values[index];
+            let new = if old < threshold { 0.0 } else { 1.0 };
+            let error = old - new;
+
+            values[index] = new;
+
+            for (dx, dy, weight) in kernel {
+                let nx = x as isize + dx;
+                let ny = y as isize + dy;
+
+                if nx < 0 || nx >= width as isize || ny >= height as isize {
+                    continue;
+                }
+
+                values[ny as usize * width + nx as usize] += error * weight;
+            }
+        }
+    }
+}
+
+fn dither(values: &mut [f32], width: usize, settings: &ImageSettings) {
+    let threshold = settings.threshold as f32 / 255.0;
+
+    match settings.dither {
+        DitherMode::FloydSteinberg => diffuse(
+            values,
+            width,
+            threshold,
+            &[
+                (1, 0, 7.0 / 16.0),
+                (-1, 1, 3.0 / 16.0),
+                (0, 1, 5.0 / 16.0),
+                (1, 1, 1.0 / 16.0),
+            ],
+        ),
+        DitherMode::Atkinson => diffuse(
+            values,
+            width,
+            threshold,
+            &[
+                (1, 0, 1.0 / 8.0),
+                (2, 0, 1.0 / 8.0),
+                (-1, 1, 1.0 / 8.0),
+                (0, 1, 1.0 / 8.0),
+                (1, 1, 1.0 / 8.0),
+                (0, 2, 1.0 / 8.0),
+            ],
+        ),
+        DitherMode::Threshold => {
+            for value in values.iter_mut() {
+                *value = if *value < threshold { 0.0 } else { 1.0 };
+            }
+        }
+    }
+}
+
+/// Turns an already decoded image into a black and white PNG that the
+/// printer can take as is.
+pub fn rasterize(
+    image: DynamicImage,
+    settings: &ImageSettings,
+    limits: &ImageLimits,
+) -> Result<Vec<u8>, ProcessError> {
+    let image = match settings.upside_down {
+        true => image.rotate180(),
+        false => image,
+    };
+
+    let gray = to_gray(&scale(image, settings, limits)?);
+    let width = gray.width();
+    let height = gray.height();
+
+    let mut values = adjust(&gray, settings);
+
+    dither(&mut values, width as usize, settings);
+
+    let output = GrayImage::from_fn(width, height, |x, y| {
+        match values[(y * width + x) as usize] < 0.5 {
+            true => Luma([0]),
+            false => Luma([255]),
+        }
+    });
+
+    Ok(encode_png(DynamicImage::ImageLuma8(output))?)
+}
+
+fn encode_png(image: DynamicImage) -> ImageResult<Vec<u8>> {
+    let mut png_data = Vec::new();
+
+    image.write_to(&mut Cursor::new(&mut png_data), ImageFormat::Png)?;
+
+    Ok(png_data)
+}
+
+/// Lays images out in a grid as wide as the paper, `columns` to a row, each
+/// scaled to fit its cell. Cells share `max_height_mm` evenly between the
+/// rows, so the whole grid stays within it. A short last row is centered.
+pub fn collage(
+    images: &[DynamicImage],
+    columns: u32,
+    gap: u32,
+    limits: &ImageLimits,
+) -> ImageResult<Vec<u8>> {
+    let columns = columns.clamp(1, images.len().max(1) as u32);
+    let cell_width = (DOTS_PER_LINE.saturating_sub(gap * (columns - 1)) / columns).max(1);
+    let row_count = (images.len() as u32).div_ceil(columns).max(1);
+    let cell_height = ((limits.max_height_mm * DOTS_PER_MM).saturating_sub(gap * (row_count - 1))
+        / row_count)
+        .max(1);
+
+    let cells: Vec<DynamicImage> = images
+        .iter()
+        .map(|image| image.resize(cell_width, cell_height, FilterType::Triangle))
+        .collect();
+
+    let rows: Vec<&[DynamicImage]> = cells.chunks(columns as usize).collect();
+    let row_heights: Vec<u32> = rows
+        .iter()
+        .map(|row| row.iter().map(DynamicImage::height).max().unwrap_or(0))
+        .collect();
+    let height = row_heights.iter().sum::<u32>() + gap * (rows.len() as u32).saturating_sub(1);
+
+    let mut canvas =
+        RgbaImage::from_pixel(DOTS_PER_LINE, height.max(1), Rgba([255, 255, 255, 255]));
+    let mut y = 0;
+
+    for (row, row_height) in rows.iter().zip(row_heights) {
+        let row_width = row.len() as u32 * cell_width + (row.len() as u32 - 1) * gap;
+        let mut x = (DOTS_PER_LINE - row_width.min(DOTS_PER_LINE)) / 2;
+
+        for cell in row.iter() {
+            let left = x + (cell_width - cell.width()) / 2;
+            let top = y + (row_height - cell.height()) / 2;
+
+            imageops::overlay(&mut canvas, &cell.to_rgba8(), left as i64, top as i64);
+            x += cell_width + gap;
+        }
+
+        y += row_height + gap;
+    }
+
+    encode_png(DynamicImage::ImageRgba8(canvas))
+}
+
+/// Lines emoji up in a centered row, each as large as the line allows up to
+/// a quarter of its width.
+pub fn emoji_row(emoji: &[DynamicImage]) -> ImageResult<Vec<u8>> {
+    const PADDING: u32 = 4;
+
+    let cell_size = (DOTS_PER_LINE / emoji.len().max(1) as u32).min(DOTS_PER_LINE / 4);
+    let mut canvas = RgbaImage::from_pixel(DOTS_PER_LINE, cell_size, Rgba([255, 255, 255, 255]));
+    let mut x = (DOTS_PER_LINE - cell_size * emoji.len() as u32) / 2;
+
+    for image in emoji {
+        let size = cell_size.saturating_sub(2 * PADDING).max(1);
+        let image = image.resize(size, size, FilterType::Triangle);
+        let left = x + (cell_size - image.width()) / 2;
+        let top = (cell_size - image.height()) / 2;
+
+        imageops::overlay(&mut canvas, &image.to_rgba8(), left as i64, top as i64);
+        x += cell_size;
+    }
+
+    encode_png(DynamicImage::ImageRgba8(canvas))
+}
+
+/// Draws a voice message waveform as a row of bars across the paper,
+/// mirrored around the middle like Discord's player.
+pub fn waveform(samples: &[u8]) -> ImageResult<Vec<u8>> {
+    const BAR_WIDTH: u32 = 4;
+    const BAR_GAP: u32 = 2;
+    let height = 12 * DOTS_PER_MM;
+
+    let bar_count = DOTS_PER_LINE / (BAR_WIDTH + BAR_GAP);
+    let mut canvas = GrayImage::from_pixel(DOTS_PER_LINE, height, Luma([255]));
+
+    for bar in 0..bar_count {
+        // Each bar takes the loudest sample in its share of the waveform.
+        let start = bar as usize * samples.len() / bar_count as usize;
+        let end = ((bar as usize + 1) * samples.len() / bar_count as usize).max(start + 1);
+        let level = samples
+            .get(start..end.min(samples.len()))
+            .and_then(|bucket| bucket.iter().max());
+
+        let bar_height = (u32::from(level.copied().unwrap_or(0)) * height / 255).max(2);
+        let top = (height - bar_height) / 2;
+        let left = bar * (BAR_WIDTH + BAR_GAP) + BAR_GAP / 2;
+
+        for y in top..top + bar_height {
+            for x in left..left + BAR_WIDTH {
+                canvas.put_pixel(x, y, Luma([0]));
+            }
+        }
+    }
+
+    encode_png(DynamicImage::ImageLuma8(canvas))
+}
+
+fn frame_indices(count: usize, mode: AnimationMode) -> Vec<usize> {
+    match mode {
+        AnimationMode::First => vec![0],
+        AnimationMode::Middle => vec![count / 2],
+        AnimationMode::Flipbook(frames) => {
+            let frames = (frames as usize).clamp(1, count.min(MAX_FLIPBOOK_FRAMES));
+
+            (0..frames).map(|index| index * count / frames).collect()
+        }
+    }
+}
+
+/// Picks the frames `mode` asks for out of an animation. `open` is called
+/// once to count frames when needed and once more to collect them, so only
+/// the chosen frames are ever held in memory.
+fn select_frames<'a, D: AnimationDecoder<'a>>(
+    open: impl Fn() -> ImageResult<D>,
+    settings: &ImageSettings,
+    limits: &ImageLimits,
+) -> ImageResult<DynamicImage> {
+    let mode = settings.animation;
+    let count = match mode {
+        AnimationMode::First => 1,
+        _ => open()?
+            .into_frames()
+            .take(MAX_FRAMES)
+            .take_while(Result::is_ok)
+            .count(),
+    };
+
+    let indices = frame_indices(count.max(1), mode);
+    let last_index = indices.last().copied().unwrap_or(0);
+
+    let frames = open()?
+        .into_frames()
+        .take(last_index + 1)
+        .enumerate()
+        .filter(|(index, _)| indices.contains(index))
+        .map(|(_, frame)| frame.map(|frame| fit_frame(frame.into_buffer(), settings, limits)))
+        .collect::<ImageResult<Vec<RgbaImage>>>()?;
+
+    stack_frames(frames, limits)
+}
+
+/// Shrinks a flipbook frame to the size it prints at as soon as it's decoded,
+/// so the strip never holds more than it prints. A lone frame is left for
+/// `rasterize`, which follows `overflow`.
+fn fit_frame(frame: RgbaImage, settings: &ImageSettings, limits: &ImageLimits) -> RgbaImage {
+    if !matches!(settings.animation, AnimationMode::Flipbook(_)) {
+        return frame;
+    }
+
+    let width = target_width(frame.width(), settings);
+    let max_height = (limits.max_height_mm * DOTS_PER_MM).max(1);
+
+    DynamicImage::ImageRgba8(frame)
+        .resize(width, max_height, FilterType::Triangle)
+        .into_rgba8()
+}
+
+/// Stacks frames into one strip. Frames that would run past `max_height_mm`
+/// are left off, though the first is always kept.
+fn stack_frames(mut frames: Vec<RgbaImage>, limits: &ImageLimits) -> ImageResult<DynamicImage> {
+    if frames.is_empty() {
+        return Err(ImageError::Parameter(ParameterError::from_kind(
+            ParameterErrorKind::NoMoreData,
+        )));
+    }
+
+    let max_height = limits.max_height_mm * DOTS_PER_MM;
+    let mut height = frames[0].height();
+    let mut kept = 1;
+
+    for frame in &frames[1..] {
+        if height + FLIPBOOK_GAP + frame.height() > max_height {
+            break;
+        }
+
+        height += FLIPBOOK_GAP + frame.height();
+        kept += 1;
+    }
+
+    frames.truncate(kept);
+
+    let width = frames.iter().map(|frame| frame.width()).max().unwrap_or(1);
+    let mut strip = RgbaImage::from_pixel(width, height, Rgba([255, 255, 255, 255]));
+    let mut y = 0;
+
+    for frame in &frames {
+        imageops::overlay(&mut strip, frame, 0, y as i64);
+        y += frame.height() + FLIPBOOK_GAP;
+    }
+
+    Ok(DynamicImage::ImageRgba8(strip))
+}
+
+fn render_lottie(
+    animation: &Animation,
+    settings: &ImageSettings,
+    limits: &ImageLimits,
+) -> ImageResult<DynamicImage> {
+    if animation.width() > limits.max_decode_width || animation.height() > limits.max_decode_height
+    {
+        return Err(ImageError::Limits(LimitError::from_kind(
+            LimitErrorKind::DimensionError,
+        )));
+    }
+
+    let frame_count = animation.frame_count().min(MAX_FRAMES);
+
+    stack_frames(
+        frame_indices(frame_count, settings.animation)
+            .into_iter()
+            .filter_map(|index| animation.render(index))
+            .map(|frame| fit_frame(frame, settings, limits))
+            .collect(),
+        limits,
+    )
+}
+
+pub fn decode(
+    data: &[u8],
+    settings: &ImageSettings,
+    limits: &ImageLimits,
+) -> ImageResult<DynamicImage> {
+    let mut decode_limits = Limits::default();
+    decode_limits.max_image_width = Some(limits.max_decode_width);
+    decode_limits.max_image_height = Some(limits.max_decode_height);
+
+    match image::guess_format(data) {
+        Ok(ImageFormat::Gif) => {
+            return select_frames(
+                || {
+                    let mut decoder = GifDecoder::new(Cursor::new(data))?;
+                    decoder.set_limits(decode_limits.clone())?;
+
+                    Ok(decoder)
+                },
+                settings,
+                limits,
+            );
+        }
+        Ok(ImageFormat::Png) if PngDecoder::new(Cursor::new(data))?.is_apng()? => {
+            return select_frames(
+                || {
+                    let mut decoder = PngDecoder::new(Cursor::new(data))?;
+                    decoder.set_limits(decode_limits.clone())?;
+
+                    decoder.apng()
+                },
+                settings,
+                limits,
+            );
+        }
+        Ok(ImageFormat::WebP) if WebPDecoder::new(Cursor::new(data))?.has_animation() => {
+            return select_frames(
+                || {
+                    let mut decoder = WebPDecoder::new(Cursor::new(data))?;
+                    decoder.set_limits(decode_limits.clone())?;
+
+                    Ok(decoder)
+                },
+                settings,
+                limits,
+            );
+        }
+        // Lottie stickers are JSON, which no image decoder recognizes.
+        Err(_) => {
+            if let Some(animation) = Animation::parse(data) {
+                return render_lottie(&animation, settings, limits);
+            }
+        }
+        _ => {}
+    }
+
+    let mut reader = ImageReader::new(Cursor::new(data)).with_guessed_format()?;
+
+    reader.limits(decode_limits);
+    reader.decode()
+}
+
+pub fn process(
+    data: &[u8],
+    settings: &ImageSettings,
+    limits: &ImageLimits,
+) -> Result<Vec<u8>, ProcessError> {
+    rasterize(decode(data, settings, limits)?, settings, limits)
+}
+
+#[cfg(test)]
+mod tests {
+    use image::{codecs::gif::GifEncoder, Frame};
+
+    use super::*;
+
+    fn limits(overflow: OverflowMode) -> ImageLimits {
+        ImageLimits {
+            overflow,
+            ..ImageLimits::default()
+        }
+    }
+
+    #[test]
+    fn thin_tall_image_is_capped_before_resizing() {
+        let settings = ImageSettings {
+            width: Some(DOTS_PER_LINE),
+            ..ImageSettings::default()
+        };
+        let image = DynamicImage::ImageLuma8(GrayImage::new(1, 4096));
+        let max_height = ImageLimits::default().max_height_mm * DOTS_PER_MM;
+
+        let cropped = scale(image.clone(), &settings, &limits(OverflowMode::Crop)).unwrap();
+        assert_eq!(
+            (cropped.width(), cropped.height()),
+            (DOTS_PER_LINE, max_height)
+        );
+
+        let downscaled = scale(image.clone(), &settings, &limits(OverflowMode::Downscale)).unwrap();
+        assert_eq!(downscaled.height(), max_height);
+        assert!(downscaled.width() < DOTS_PER_LINE);
+
+        assert!(matches!(
+            scale(image, &settings, &limits(OverflowMode::Notice)),
+            Err(ProcessError::OverLimit)
+        ));
+    }
+
+    #[test]
+    fn flipbook_is_capped_in_frames_and_height() {
+        assert_eq!(frame_indices(1000, AnimationMode::Flipbook(1000)).len(), 16);
+
+        let settings = ImageSettings {
+            animation: AnimationMode::Flipbook(16),
+            ..ImageSettings::default()
+        };
+        let limits = ImageLimits::default();
+        let frames = (0..16)
+            .map(|_| fit_frame(RgbaImage::new(640, 640), &settings, &limits))
+            .collect();
+        let strip = stack_frames(frames, &limits).unwrap();
+
+        assert_eq!(strip.width(), DOTS_PER_LINE);
+        assert!(strip.height() <= limits.max_height_mm * DOTS_PER_MM);
+    }
+
+    #[test]
+    fn collage_of_tall_images_stays_within_height() {
+        let limits = ImageLimits::default();
+        let images = vec![DynamicImage::ImageLuma8(GrayImage::new(1, 4096)); 3];
+        let collage = image::load_from_memory(&collage(&images, 2, 8, &limits).unwrap()).unwrap();
+
+        assert_eq!(collage.width(), DOTS_PER_LINE);
+        assert!(collage.height() <= limits.max_height_mm * DOTS_PER_MM);
+    }
+
+    #[test]
+    fn frame_indices_are_evenly_spaced() {
+        assert_eq!(frame_indices(10, AnimationMode::First), vec![0]);
+        assert_eq!(frame_indices(10, AnimationMode::Middle), vec![5]);
+        assert_eq!(frame_indices(9, AnimationMode::Flipbook(3)), vec![0, 3, 6]);
+        assert_eq!(frame_indices(2, AnimationMode::Flipbook(5)), vec![0, 1]);
+    }
+
+    #[test]
+    fn gif_frames_are_selected() {
+        let colors = [[255, 0, 0, 255], [0, 255, 0, 255], [0, 0, 255, 255]];
+        let mut data = Vec::new();
+
+        GifEncoder::new(&mut data)
+            .encode_frames(
+                colors
+                    .iter()
+                    .map(|color| Frame::new(RgbaImage::from_pixel(4, 4, Rgba(*color)))),
+            )
+            .unwrap();
+
+        let decode_with = |animation| {
+            let settings = ImageSettings {
+                animation,
+                ..ImageSettings::default()
+            };
+
+            decode(&data, &settings, &ImageLimits::default())
+                .unwrap()
+                .to_rgba8()
+        };
+
+        assert_eq!(
+            decode_with(AnimationMode::Middle).get_pixel(0, 0).0,
+            colors[1]
+        );
+
+        let flipbook = decode_with(AnimationMode::Flipbook(3));
+        assert_eq!(flipbook.height(), 3 * 4 + 2 * FLIPBOOK_GAP);
+        assert_eq!(flipbook.get_pixel(0, 4 + FLIPBOOK_GAP).0, colors[1]);
+    }
+
+    #[test]
+    fn lottie_is_decoded() {
+        let data = serde_json::json!({
+            "w": 20,
+            "h": 10,
+            "ip": 0,
+            "op": 4,
+            "layers": [{ "ty": 1, "ks": {}, "sw": 20, "sh": 10, "sc": "#000000" }],
+        });
+        let settings = ImageSettings {
+            animation: AnimationMode::Flipbook(2),
+            ..ImageSettings::default()
+        };
+
+        let image = decode(
+            &serde_json::to_vec(&data).unwrap(),
+            &settings,
+            &ImageLimits::default(),
+        )
+        .unwrap()
+        .to_rgba8();
+
+        assert_eq!((image.width(), image.height()), (20, 2 * 10 + FLIPBOOK_GAP));
+        assert_eq!(image.get_pixel(5, 5).0, [0, 0, 0, 255]);
+    }
+}
@@ -0,0 +1,550 @@
+use std::mem;
+
+use twemoji_assets::png::PngTwemojiAsset;
+use unicode_width::UnicodeWidthChar;
+
+use crate::{
+    Font, JustifyMode, PrinterInstruction, PrinterMessage, TextMode, UnderlineMode, CHARS_PER_LINE,
+};
+
+const TAB_STOP: usize = 8;
+
+/// The columns `text` takes up, counted a character at a time the way the
+/// printer advances. Measuring whole strings would count emoji sequences as
+/// one glyph, which the printer doesn't draw them as.
+pub fn display_width(text: &str) -> usize {
+    text.chars().map(|c| c.width().unwrap_or(0)).sum()
+}
+
+/// Hard-wraps `text` into pieces no wider than `columns`, for content like
+/// code where word boundaries don't matter. Always returns at least one piece.
+pub fn split_columns(text: &str, columns: usize) -> Vec<String> {
+    let mut pieces = vec![String::new()];
+    let mut width = 0;
+
+    for c in text.chars() {
+        let char_width = c.width().unwrap_or(0);
+
+        if width + char_width > columns && width > 0 {
+            pieces.push(String::new());
+            width = 0;
+        }
+
+        if let Some(piece) = pieces.last_mut() {
+            piece.push(c);
+        }
+
+        width += char_width;
+    }
+
+    pieces
+}
+
+fn is_style(instruction: &PrinterInstruction) -> bool {
+    matches!(
+        instruction,
+        PrinterInstruction::Bold(_)
+            | PrinterInstruction::Italic(_)
+            | PrinterInstruction::Underline(_)
+            | PrinterInstruction::Strike(_)
+            | PrinterInstruction::Reverse(_)
+            | PrinterInstruction::Justify(_)
+            | PrinterInstruction::Size { .. }
+            | PrinterInstruction::Font(_)
+    )
+}
+
+/// Replaces the style of the same kind as `instruction` in `styles`.
+fn set_style(styles: &mut Vec<PrinterInstruction>, instruction: &PrinterInstruction) {
+    if !is_style(instruction) {
+        return;
+    }
+
+    styles.retain(|style| mem::discriminant(style) != mem::discriminant(instruction));
+    styles.push(instruction.clone());
+}
+
+/// Reverses the order of already wrapped lines. Each line starts by setting
+/// every style to what it was at that point, since the lines before it no
+/// longer come first.
+fn reverse_lines(segment: PrinterMessage, styles: &[PrinterInstruction]) -> PrinterMessage {
+    let mut styles = styles.to_vec();
+    let mut lines = vec![styles.clone()];
+
+    for instruction in segment {
+        let PrinterInstruction::Text(text) = instruction else {
+            set_style(&mut styles, &instruction);
+
+            if let Some(line) = lines.last_mut() {
+                line.push(instruction);
+            }
+
+            continue;
+        };
+
+        for (index, part) in text.split('\n').enumerate() {
+            if index > 0 {
+                if let Some(line) = lines.last_mut() {
+                    line.push(PrinterInstruction::Text("\n".to_string()));
+                }
+
+                lines.push(styles.clone());
+            }
+
+            if let (false, Some(line)) = (part.is_empty(), lines.last_mut()) {
+                line.push(PrinterInstruction::Text(part.to_string()));
+            }
+        }
+    }
+
+    // The segment ends at the start of a line, so the last entry only holds
+    // the styles that were in effect after it.
+    let end = lines.pop().unwrap_or_default();
+
+    lines.into_iter().rev().flatten().chain(end).collect()
+}
+
+struct IndentLevel {
+    first_line: String,
+    hanging: String,
+    started: bool,
+}
+
+struct Layout {
+    output: PrinterMessage,
+    indents: Vec<IndentLevel>,
+    text_mode: TextMode,
+    /// The current value of every style, so lines can be reordered.
+    styles: Vec<PrinterInstruction>,
+    /// Where upside down text began in the output, and the styles then.
+    upside_down: Option<(usize, Vec<PrinterInstruction>)>,
+    font: Font,
+    char_scale: u8,
+    column: usize,
+    at_line_start: bool,
+    /// Where the most recent space on the current line was written, as
+    /// (output index, byte offset, column after it), so it can become a break.
+    last_space: Option<(usize, usize, usize)>,
+}
+
+impl Layout {
+    fn new() -> Layout {
+        Layout {
+            output: PrinterMessage::new(),
+            indents: Vec::new(),
+            text_mode: TextMode::Native,
+            styles: vec![
+                PrinterInstruction::Bold(false),
+                PrinterInstruction::Italic(false),
+                PrinterInstruction::Underline(UnderlineMode::None),
+                PrinterInstruction::Strike(false),
+                PrinterInstruction::Reverse(false),
+                PrinterInstruction::Justify(JustifyMode::Left),
+                PrinterInstruction::Size {
+                    width: 1,
+                    height: 1,
+                },
+                PrinterInstruction::Font(Font::A),
+            ],
+            upside_down: None,
+            font: Font::A,
+            char_scale: 1,
+            column: 0,
+            at_line_start: true,
+            last_space: None,
+        }
+    }
+
+    /// Raster text has a single font, so only native text changes width.
+    fn line_width(&self) -> usize {
+        match self.text_mode {
+            TextMode::Native => (self.font.chars_per_line() / self.char_scale).into(),
+            TextMode::Raster => CHARS_PER_LINE.into(),
+        }
+    }
+
+    fn text(&mut self) -> &mut String {
+        if !matches!(self.output.last(), Some(PrinterInstruction::Text(_))) {
+            self.output.push(PrinterInstruction::Text(String::new()));
+        }
+
+        let Some(PrinterInstruction::Text(text)) = self.output.last_mut() else {
+            unreachable!();
+        };
+
+        text
+    }
+
+    fn prefix(&mut self) -> String {
+        let mut prefix = String::new();
+
+        for indent in &mut self.indents {
+            match indent.started {
+                true => prefix.push_str(&indent.hanging),
+                false => prefix.push_str(&indent.first_line),
+            }
+
+            indent.started = true;
+        }
+
+        prefix
+    }
+
+    fn char_width(&self, c: char) -> usize {
+        match c {
+            '\t' => TAB_STOP - self.column % TAB_STOP,
+            c => c.width().unwrap_or(0),
+        }
+    }
+
+    fn reset_line(&mut self) {
+        self.column = 0;
+        self.at_line_start = true;
+        self.last_space = None;
+    }
+
+    /// Blocks and justification only take effect at the start of a line.
+    fn start_line(&mut self) {
+        if !self.at_line_start {
+            self.write_char('\n');
+        }
+    }
+
+    fn wrap(&mut self) {
+        let prefix = self.prefix();
+
+        match self.last_space.take() {
+            Some((index, offset, column_after)) => {
+                if let PrinterInstruction::Text(text) = &mut self.output[index] {
+                    text.replace_range(offset..offset + 1, &format!("\n{}", prefix));
+                }
+
+                self.column = display_width(&prefix) + self.column.saturating_sub(column_after);
+            }
+            None => {
+                self.text().push_str(&format!("\n{}", prefix));
+                self.column = display_width(&prefix);
+            }
+        }
+    }
+
+    fn write_char(&mut self, c: char) {
+        if self.at_line_start {
+            self.at_line_start = false;
+
+            if !self.indents.is_empty() {
+                let prefix = self.prefix();
+                let prefix = match c {
+                    '\n' => prefix.trim_end().to_string(),
+                    _ => prefix,
+                };
+
+                self.column = display_width(&prefix);
+                self.text().push_str(&prefix);
+            }
+        }
+
+        if c == '\n' {
+            self.text().push('\n');
+            self.reset_line();
+            return;
+        }
+
+        let width = self.char_width(c);
+
+        if self.column + width > self.line_width() && self.column > 0 {
+            if c == ' ' {
+                self.last_space = None;
+                self.wrap();
+                return;
+            }
+
+            self.wrap();
+        }
+
+        let text = self.text();
+        let offset = text.len();
+        text.push(c);
+
+        self.column += width;
+
+        if c == ' ' {
+            self.last_space = Some((self.output.len() - 1, offset, self.column));
+        }
+
+        // The worker feeds a line before each emoji image, so the next
+        // character starts a fresh line.
+        if self.text_mode == TextMode::Native
+            && PngTwemojiAsset::from_emoji(&c.to_string()).is_some()
+        {
+            self.reset_line();
+        }
+    }
+}
+
+/// Wraps text on word boundaries using its display width, and applies the
+/// prefixes of `Indent` blocks to every line inside them.
+pub fn layout(message: PrinterMessage) -> PrinterMessage {
+    let mut layout = Layout::new();
+
+    for instruction in message {
+        match instruction {
+            PrinterInstruction::Text(text) => {
+                for c in text.chars() {
+                    layout.write_char(c);
+                }
+            }
+            PrinterInstruction::Indent {
+                first_line,
+                hanging,
+            } => {
+                layout.start_line();
+                layout.indents.push(IndentLevel {
+                    first_line,
+                    hanging,
+                    started: false,
+                });
+            }
+            PrinterInstruction::Dedent => {
+                layout.start_line();
+                layout.indents.pop();
+            }
+            PrinterInstruction::Justify(_) => {
+                layout.start_line();
+                set_style(&mut layout.styles, &instruction);
+                layout.output.push(instruction);
+            }
+            PrinterInstruction::Size { width, .. } => {
+                layout.char_scale = width.clamp(1, 8);
+                set_style(&mut layout.styles, &instruction);
+                layout.output.push(instruction);
+            }
+            PrinterInstruction::Font(font) => {
+                layout.font = font;
+                set_style(&mut layout.styles, &instruction);
+                layout.output.push(instruction);
+            }
+            // Raster text is turned as a whole image instead, which already
+            // puts its lines in the right order.
+            PrinterInstruction::UpsideDown(true) => {
+                layout.start_line();
+                layout.output.push(instruction);
+
+                if layout.text_mode == TextMode::Native {
+                    layout.upside_down = Some((layout.output.len(), layout.styles.clone()));
+                }
+            }
+            PrinterInstruction::UpsideDown(false) => {
+                layout.start_line();
+
+                if let Some((start, styles)) = layout.upside_down.take() {
+                    let segment = layout.output.split_off(start);
+
+                    layout.output.extend(reverse_lines(segment, &styles));
+                }
+
+                layout.output.push(instruction);
+            }
+            PrinterInstruction::TextMode(mode) => {
+                layout.text_mode = mode;
+                layout.output.push(instruction);
+            }
+            PrinterInstruction::Image(_)
+            | PrinterInstruction::Bitmap(_)
+            | PrinterInstruction::QrCode(_)
+            | PrinterInstruction::Barcode(_)
+            | PrinterInstruction::PrintCut => {
+                layout.reset_line();
+                layout.output.push(instruction);
+            }
+            instruction => {
+                set_style(&mut layout.styles, &instruction);
+                layout.output.push(instruction);
+            }
+        }
+    }
+
+    layout.output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn printed(message: &PrinterMessage) -> String {
+        message
+            .iter()
+            .filter_map(|instruction| match instruction {
+                PrinterInstruction::Text(text) => Some(text.as_str()),
+                _ => None,
+            })
+            .collect()
+    }
+
+    fn text(text: &str) -> PrinterInstruction {
+        PrinterInstruction::Text(text.to_string())
+    }
+
+    #[test]
+    fn wraps_at_last_space_before_width() {
+        let output = layout(vec![text(&format!(
+            "{} {}",
+            "a".repeat(40),
+            "b".repeat(10)
+        ))]);
+
+        assert_eq!(
+            printed(&output),
+            format!("{}\n{}", "a".repeat(40), "b".repeat(10))
+        );
+    }
+
+    #[test]
+    fn drops_space_that_falls_on_the_edge() {
+        let output = layout(vec![text(&format!("{} x", "a".repeat(48)))]);
+
+        assert_eq!(printed(&output), format!("{}\nx", "a".repeat(48)));
+    }
+
+    #[test]
+    fn breaks_words_longer_than_a_line() {
+        let output = layout(vec![text(&"a".repeat(50))]);
+
+        assert_eq!(printed(&output), format!("{}\naa", "a".repeat(48)));
+    }
+
+    #[test]
+    fn hanging_indent_counts_prefix_width() {
+        let output = layout(vec![
+            PrinterInstruction::Indent {
+                first_line: "- ".to_string(),
+                hanging: "  ".to_string(),
+            },
+            text(&["a", "b", "c"].map(|c| c.repeat(30)).join(" ")),
+            PrinterInstruction::Dedent,
+        ]);
+
+        assert_eq!(
+            printed(&output),
+            format!(
+                "- {}\n  {}\n  {}\n",
+                "a".repeat(30),
+                "b".repeat(30),
+                "c".repeat(30)
+            )
+        );
+    }
+
+    #[test]
+    fn nested_indents_stack_prefixes() {
+        let output = layout(vec![
+            PrinterInstruction::Indent {
+                first_line: "| ".to_string(),
+                hanging: "| ".to_string(),
+            },
+            PrinterInstruction::Indent {
+                first_line: "1. ".to_string(),
+                hanging: "   ".to_string(),
+            },
+            text(&format!("{} {}", "a".repeat(40), "b".repeat(5))),
+            PrinterInstruction::Dedent,
+            PrinterInstruction::Dedent,
+        ]);
+
+        assert_eq!(
+            printed(&output),
+            format!("| 1. {}\n|    {}\n", "a".repeat(40), "b".repeat(5))
+        );
+    }
+
+    #[test]
+    fn wide_characters_take_two_columns() {
+        let output = layout(vec![text(&"日".repeat(25))]);
+
+        assert_eq!(printed(&output), format!("{}\n日", "日".repeat(24)));
+    }
+
+    #[test]
+    fn tabs_advance_to_the_next_stop() {
+        let output = layout(vec![text(&format!("a\t{} c", "b".repeat(39)))]);
+
+        assert_eq!(printed(&output), format!("a\t{}\nc", "b".repeat(39)));
+    }
+
+    #[test]
+    fn wraps_at_space_in_an_earlier_instruction() {
+        let output = layout(vec![
+            text(&format!("{} ", "a".repeat(40))),
+            PrinterInstruction::Bold(true),
+            text(&"b".repeat(10)),
+            PrinterInstruction::Bold(false),
+        ]);
+
+        assert!(
+            matches!(&output[0], PrinterInstruction::Text(text) if *text == format!("{}\n", "a".repeat(40)))
+        );
+        assert!(matches!(output[1], PrinterInstruction::Bold(true)));
+        assert_eq!(
+            printed(&output),
+            format!("{}\n{}", "a".repeat(40), "b".repeat(10))
+        );
+    }
+
+    #[test]
+    fn double_width_halves_the_line() {
+        let output = layout(vec![
+            PrinterInstruction::Size {
+                width: 2,
+                height: 2,
+            },
+            text(&format!("{} {}", "a".repeat(20), "b".repeat(5))),
+        ]);
+
+        assert_eq!(
+            printed(&output),
+            format!("{}\n{}", "a".repeat(20), "b".repeat(5))
+        );
+    }
+
+    #[test]
+    fn upside_down_reverses_lines_and_restores_styles() {
+        let output = layout(vec![
+            PrinterInstruction::UpsideDown(true),
+            text("first\n"),
+            PrinterInstruction::Bold(true),
+            text("second\n"),
+            PrinterInstruction::Bold(false),
+            PrinterInstruction::UpsideDown(false),
+        ]);
+
+        assert_eq!(printed(&output), "second\nfirst\n");
+
+        // The second line now comes first, so it has to turn bold itself,
+        // and the first line has to turn it off again.
+        let second = output
+            .iter()
+            .position(|instruction| matches!(instruction, PrinterInstruction::Text(text) if text == "second"))
+            .unwrap();
+        let first = output
+            .iter()
+            .position(|instruction| matches!(instruction, PrinterInstruction::Text(text) if text == "first"))
+            .unwrap();
+
+        assert!(output[..second]
+            .iter()
+            .any(|instruction| matches!(instruction, PrinterInstruction::Bold(true))));
+        assert!(output[second..first]
+            .iter()
+            .any(|instruction| matches!(instruction, PrinterInstruction::Bold(false))));
+    }
+
+    #[test]
+    fn split_pieces_fit_their_measured_width() {
+        // "❤️" is one column a character at a time, but two as a string.
+        for line in [format!("{}❤️", "a".repeat(43)), "👨‍👩‍👧".repeat(4)] {
+            for piece in split_columns(&line, 44) {
+                assert!(display_width(&piece) <= 44, "{:?}", piece);
+            }
+        }
+    }
+}
@@ -0,0 +1,665 @@
+use image::{Rgba, RgbaImage};
+use serde_json::Value;
+use tiny_skia::{
+    Color, FillRule, LineCap, LineJoin, Paint, Path, PathBuilder, Pixmap, Rect, Stroke, Transform,
+};
+
+/// How deep precompositions may nest, which also stops ones that include
+/// themselves.
+const MAX_DEPTH: usize = 8;
+
+/// Control point distance for approximating a quarter circle with a cubic.
+const KAPPA: f32 = 0.552_284_8;
+
+/// A Lottie animation, drawn one still frame at a time. Only what stickers
+/// commonly use is supported: shape, solid and precomposition layers with
+/// parenting, and rectangle, ellipse and path shapes with solid fills and
+/// strokes. Gradients are drawn in their first color; masks, mattes, trim
+/// paths and text are skipped.
+pub struct Animation {
+    root: Value,
+    width: u32,
+    height: u32,
+    in_point: f64,
+    out_point: f64,
+}
+
+impl Animation {
+    pub fn parse(data: &[u8]) -> Option<Animation> {
+        let root: Value = serde_json::from_slice(data).ok()?;
+
+        root["layers"].as_array()?;
+
+        Some(Animation {
+            width: root["w"].as_u64()?.try_into().ok()?,
+            height: root["h"].as_u64()?.try_into().ok()?,
+            in_point: root["ip"].as_f64().unwrap_or(0.0),
+            out_point: root["op"].as_f64().unwrap_or(0.0),
+            root,
+        })
+    }
+
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    pub fn frame_count(&self) -> usize {
+        (self.out_point - self.in_point).max(1.0) as usize
+    }
+
+    /// Draws frame `index`, counted from the animation's in point.
+    pub fn render(&self, index: usize) -> Option<RgbaImage> {
+        let mut pixmap = Pixmap::new(self.width, self.height)?;
+
+        draw_layers(
+            &mut pixmap,
+            &self.root,
+            self.root["layers"].as_array()?,
+            self.in_point + index as f64,
+            Transform::identity(),
+            1.0,
+            0,
+        );
+
+        Some(RgbaImage::from_fn(self.width, self.height, |x, y| {
+            let index = (y * self.width + x) as usize;
+            let color = pixmap.pixels()[index].demultiply();
+
+            Rgba([color.red(), color.green(), color.blue(), color.alpha()])
+        }))
+    }
+}
+
+fn numbers(value: &Value) -> Vec<f64> {
+    match value {
+        Value::Number(number) => number.as_f64().into_iter().collect(),
+        Value::Array(values) => values.iter().filter_map(Value::as_f64).collect(),
+        _ => Vec::new(),
+    }
+}
+
+fn is_animated(property: &Value) -> bool {
+    property["k"]
+        .as_array()
+        .and_then(|keyframes| keyframes.first())
+        .is_some_and(|keyframe| keyframe.get("t").is_some())
+}
+
+/// The keyframe that is active at `frame` and the one after it, if any.
+fn keyframes_at(property: &Value, frame: f64) -> Option<(&Value, Option<&Value>)> {
+    let keyframes = property["k"].as_array()?;
+    let index = keyframes
+        .iter()
+        .rposition(|keyframe| {
+            keyframe.get("s").is_some() && keyframe["t"].as_f64().is_some_and(|time| time <= frame)
+        })
+        .unwrap_or(0);
+
+    Some((keyframes.get(index)?, keyframes.get(index + 1)))
+}
+
+/// The value of a numeric property at `frame`, interpolated linearly between
+/// keyframes. Easing curves are ignored.
+fn value_at(property: &Value, frame: f64) -> Vec<f64> {
+    // Positions can be split into separately animated components.
+    if property["s"].as_bool() == Some(true) {
+        return ["x", "y"]
+            .iter()
+            .filter_map(|axis| value_at(&property[*axis], frame).first().copied())
+            .collect();
+    }
+
+    if !is_animated(property) {
+        return numbers(&property["k"]);
+    }
+
+    let Some((keyframe, next)) = keyframes_at(property, frame) else {
+        return Vec::new();
+    };
+
+    let start = numbers(&keyframe["s"]);
+
+    // Older files give each keyframe an explicit end value instead.
+    let end = match (&keyframe["e"], next) {
+        (Value::Null, Some(next)) => numbers(&next["s"]),
+        (end, _) => numbers(end),
+    };
+
+    let start_time = keyframe["t"].as_f64().unwrap_or(0.0);
+    let end_time = next
+        .and_then(|next| next["t"].as_f64())
+        .unwrap_or(start_time);
+
+    if start.is_empty() || keyframe["h"].as_i64() == Some(1) || end_time <= start_time {
+        return match start.is_empty() {
+            true => end,
+            false => start,
+        };
+    }
+
+    let progress = ((frame - start_time) / (end_time - start_time)).clamp(0.0, 1.0);
+
+    start
+        .iter()
+        .zip(end.iter().chain(std::iter::repeat(&0.0)))
+        .map(|(start, end)| start + (end - start) * progress)
+        .collect()
+}
+
+fn component(values: &[f64], index: usize, default: f64) -> f32 {
+    values.get(index).copied().unwrap_or(default) as f32
+}
+
+/// A transform (`ks` on layers, `tr` in shape groups) and its opacity.
+fn transform_at(transform: &Value, frame: f64) -> (Transform, f32) {
+    let anchor = value_at(&transform["a"], frame);
+    let position = value_at(&transform["p"], frame);
+    let scale = value_at(&transform["s"], frame);
+    let rotation = value_at(&transform["r"], frame);
+    let opacity = value_at(&transform["o"], frame);
+
+    let matrix =
+        Transform::from_translate(component(&position, 0, 0.0), component(&position, 1, 0.0))
+            .pre_rotate(component(&rotation, 0, 0.0))
+            .pre_scale(
+                component(&scale, 0, 100.0) / 100.0,
+                component(&scale, 1, 100.0) / 100.0,
+            )
+            .pre_translate(-component(&anchor, 0, 0.0), -component(&anchor, 1, 0.0));
+
+    (matrix, component(&opacity, 0, 100.0) / 100.0)
+}
+
+/// A layer's transform including those of its parents.
+fn layer_transform(layers: &[Value], layer: &Value, frame: f64, depth: usize) -> (Transform, f32) {
+    let (transform, opacity) = transform_at(&layer["ks"], frame);
+
+    let parent = layer["parent"].as_i64().and_then(|parent| {
+        layers
+            .iter()
+            .find(|candidate| candidate["ind"].as_i64() == Some(parent))
+    });
+
+    match parent {
+        Some(parent) if depth < MAX_DEPTH => {
+            // Parents pass on their transform but not their opacity.
+            let (parent_transform, _) = layer_transform(layers, parent, frame, depth + 1);
+
+            (parent_transform.pre_concat(transform), opacity)
+        }
+        _ => (transform, opacity),
+    }
+}
+
+fn draw_layers(
+    pixmap: &mut Pixmap,
+    root: &Value,
+    layers: &[Value],
+    frame: f64,
+    transform: Transform,
+    opacity: f32,
+    depth: usize,
+) {
+    if depth > MAX_DEPTH {
+        return;
+    }
+
+    // The first layer is on top.
+    for layer in layers.iter().rev() {
+        let in_point = layer["ip"].as_f64().unwrap_or(f64::MIN);
+        let out_point = layer["op"].as_f64().unwrap_or(f64::MAX);
+
+        // Track mattes are only drawn through the layer they belong to.
+        if layer["hd"].as_bool() == Some(true)
+            || layer["td"].as_i64().is_some_and(|matte| matte != 0)
+            || frame < in_point
+            || frame >= out_point
+        {
+            continue;
+        }
+
+        let (layer_transform, layer_opacity) = layer_transform(layers, layer, frame, 0);
+        let transform = transform.pre_concat(layer_transform);
+        let opacity = opacity * layer_opacity;
+
+        match layer["ty"].as_i64() {
+            Some(0) => {
+                let reference = layer["refId"].as_str();
+                let Some(asset) = root["assets"].as_array().and_then(|assets| {
+                    assets
+                        .iter()
+                        .find(|asset| asset["id"].as_str() == reference)
+                }) else {
+                    continue;
+                };
+
+                let Some(asset_layers) = asset["layers"].as_array() else {
+                    continue;
+                };
+
+                let start_time = layer["st"].as_f64().unwrap_or(0.0);
+                let stretch = layer["sr"].as_f64().unwrap_or(1.0).max(0.01);
+
+                draw_layers(
+                    pixmap,
+                    root,
+                    asset_layers,
+                    (frame - start_time) / stretch,
+                    transform,
+                    opacity,
+                    depth + 1,
+                );
+            }
+            Some(1) => {
+                let (Some(width), Some(height)) = (layer["sw"].as_f64(), layer["sh"].as_f64())
+                else {
+                    continue;
+                };
+
+                let Some(color) = layer["sc"].as_str().and_then(hex_color) else {
+                    continue;
+                };
+
+                let Some(rect) = Rect::from_xywh(0.0, 0.0, width as f32, height as f32) else {
+                    continue;
+                };
+
+                let mut paint = Paint::default();
+                paint.set_color(with_opacity(color, opacity));
+
+                pixmap.fill_rect(rect, &paint, transform, None);
+            }
+            Some(4) => {
+                if let Some(shapes) = layer["shapes"].as_array() {
+                    draw_shapes(pixmap, shapes, &[], frame, transform, opacity);
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+fn hex_color(hex: &str) -> Option<Color> {
+    let hex = hex.trim_start_matches('#');
+    let channel = |index: usize| u8::from_str_radix(hex.get(index..index + 2)?, 16).ok();
+
+    Some(Color::from_rgba8(
+        channel(0)?,
+        channel(2)?,
+        channel(4)?,
+        255,
+    ))
+}
+
+fn with_opacity(mut color: Color, opacity: f32) -> Color {
+    color.apply_opacity(opacity);
+    color
+}
+
+/// Colors are usually 0 to 1, but some exporters write 0 to 255.
+fn color_at(property: &Value, frame: f64) -> Option<Color> {
+    let values = value_at(property, frame);
+    let scale = match values.iter().take(3).any(|value| *value > 1.0) {
+        true => 255.0,
+        false => 1.0,
+    };
+
+    Color::from_rgba(
+        (component(&values, 0, 0.0) / scale).clamp(0.0, 1.0),
+        (component(&values, 1, 0.0) / scale).clamp(0.0, 1.0),
+        (component(&values, 2, 0.0) / scale).clamp(0.0, 1.0),
+        1.0,
+    )
+}
+
+/// Gradient stops are stored flat as offset, red, green, blue; this takes the
+/// color of the first stop.
+fn gradient_color(gradient: &Value, frame: f64) -> Option<Color> {
+    let stops = value_at(&gradient["g"]["k"], frame);
+
+    Color::from_rgba(
+        component(&stops, 1, 0.0).clamp(0.0, 1.0),
+        component(&stops, 2, 0.0).clamp(0.0, 1.0),
+        component(&stops, 3, 0.0).clamp(0.0, 1.0),
+        1.0,
+    )
+}
+
+fn shape_path(shape: &Value, frame: f64) -> Option<Path> {
+    match shape["ty"].as_str()? {
+        "rc" => {
+            let position = value_at(&shape["p"], frame);
+            let size = value_at(&shape["s"], frame);
+            let (width, height) = (component(&size, 0, 0.0), component(&size, 1, 0.0));
+            let left = component(&position, 0, 0.0) - width / 2.0;
+            let top = component(&position, 1, 0.0) - height / 2.0;
+            let radius = component(&value_at(&shape["r"], frame), 0, 0.0)
+                .min(width / 2.0)
+                .min(height / 2.0);
+
+            if radius <= 0.0 {
+                return Some(PathBuilder::from_rect(Rect::from_xywh(
+                    left, top, width, height,
+                )?));
+            }
+
+            let (right, bottom) = (left + width, top + height);
+            let handle = radius * KAPPA;
+            let mut builder = PathBuilder::new();
+
+            builder.move_to(left + radius, top);
+            builder.line_to(right - radius, top);
+            builder.cubic_to(
+                right - radius + handle,
+                top,
+                right,
+                top + radius - handle,
+                right,
+                top + radius,
+            );
+            builder.line_to(right, bottom - radius);
+            builder.cubic_to(
+                right,
+                bottom - radius + handle,
+                right - radius + handle,
+                bottom,
+                right - radius,
+                bottom,
+            );
+            builder.line_to(left + radius, bottom);
+            builder.cubic_to(
+                left + radius - handle,
+                bottom,
+                left,
+                bottom - radius + handle,
+                left,
+                bottom - radius,
+            );
+            builder.line_to(left, top + radius);
+            builder.cubic_to(
+                left,
+                top + radius - handle,
+                left + radius - handle,
+                top,
+                left + radius,
+                top,
+            );
+            builder.close();
+
+            builder.finish()
+        }
+        "el" => {
+            let position = value_at(&shape["p"], frame);
+            let size = value_at(&shape["s"], frame);
+            let (width, height) = (component(&size, 0, 0.0), component(&size, 1, 0.0));
+
+            PathBuilder::from_oval(Rect::from_xywh(
+                component(&position, 0, 0.0) - width / 2.0,
+                component(&position, 1, 0.0) - height / 2.0,
+                width,
+                height,
+            )?)
+        }
+        "sh" => {
+            // Paths aren't interpolated; each keyframe holds until the next.
+            let geometry = match is_animated(&shape["ks"]) {
+                true => {
+                    let (keyframe, _) = keyframes_at(&shape["ks"], frame)?;
+
+                    match &keyframe["s"] {
+                        Value::Array(values) => values.first()?,
+                        value => value,
+                    }
+                }
+                false => &shape["ks"]["k"],
+            };
+
+            let point = |key: &str, index: usize| -> Option<(f32, f32)> {
+                let values = numbers(geometry[key].get(index)?);
+
+                Some((component(&values, 0, 0.0), component(&values, 1, 0.0)))
+            };
+
+            let count = geometry["v"].as_array()?.len();
+            let (start_x, start_y) = point("v", 0)?;
+            let mut builder = PathBuilder::new();
+
+            builder.move_to(start_x, start_y);
+
+            let closed = geometry["c"].as_bool() == Some(true);
+            let segments = match closed {
+                true => count,
+                false => count.saturating_sub(1),
+            };
+
+            for index in 0..segments {
+                let next = (index + 1) % count;
+                let (from_x, from_y) = point("v", index)?;
+                let (out_x, out_y) = point("o", index).unwrap_or_default();
+                let (to_x, to_y) = point("v", next)?;
+                let (in_x, in_y) = point("i", next).unwrap_or_default();
+
+                builder.cubic_to(
+                    from_x + out_x,
+                    from_y + out_y,
+                    to_x + in_x,
+                    to_y + in_y,
+                    to_x,
+                    to_y,
+                );
+            }
+
+            if closed {
+                builder.close();
+            }
+
+            builder.finish()
+        }
+        _ => None,
+    }
+}
+
+fn paint_path(
+    pixmap: &mut Pixmap,
+    path: &Path,
+    style: &Value,
+    frame: f64,
+    transform: Transform,
+    opacity: f32,
+) {
+    let kind = style["ty"].as_str().unwrap_or_default();
+
+    let color = match kind {
+        "fl" | "st" => color_at(&style["c"], frame),
+        "gf" | "gs" => gradient_color(style, frame),
+        _ => None,
+    };
+
+    let Some(color) = color else {
+        return;
+    };
+
+    let style_opacity = component(&value_at(&style["o"], frame), 0, 100.0) / 100.0;
+    let mut paint = Paint::default();
+    paint.set_color(with_opacity(color, opacity * style_opacity));
+
+    match kind {
+        "fl" | "gf" => {
+            let fill_rule = match style["r"].as_i64() {
+                Some(2) => FillRule::EvenOdd,
+                _ => FillRule::Winding,
+            };
+
+            pixmap.fill_path(path, &paint, fill_rule, transform, None);
+        }
+        _ => {
+            let stroke = Stroke {
+                width: component(&value_at(&style["w"], frame), 0, 1.0),
+                line_cap: match style["lc"].as_i64() {
+                    Some(2) => LineCap::Round,
+                    Some(3) => LineCap::Square,
+                    _ => LineCap::Butt,
+                },
+                line_join: match style["lj"].as_i64() {
+                    Some(2) => LineJoin::Round,
+                    Some(3) => LineJoin::Bevel,
+                    _ => LineJoin::Miter,
+                },
+                miter_limit: style["ml"].as_f64().unwrap_or(4.0) as f32,
+                ..Stroke::default()
+            };
+
+            pixmap.stroke_path(path, &paint, &stroke, transform, None);
+        }
+    }
+}
+
+/// Draws a list of shape items. A fill or stroke applies to every shape
+/// listed before it in its group and in the groups nested there, and items
+/// listed first are drawn on top.
+fn draw_shapes(
+    pixmap: &mut Pixmap,
+    items: &[Value],
+    inherited_styles: &[&Value],
+    frame: f64,
+    transform: Transform,
+    opacity: f32,
+) {
+    let (transform, opacity) = match items.iter().find(|item| item["ty"] == "tr") {
+        Some(group_transform) => {
+            let (group_transform, group_opacity) = transform_at(group_transform, frame);
+
+            (
+                transform.pre_concat(group_transform),
+                opacity * group_opacity,
+            )
+        }
+        None => (transform, opacity),
+    };
+
+    for (index, item) in items.iter().enumerate().rev() {
+        if item["hd"].as_bool() == Some(true) {
+            continue;
+        }
+
+        let styles: Vec<&Value> = items[index + 1..]
+            .iter()
+            .filter(|style| {
+                matches!(style["ty"].as_str(), Some("fl" | "st" | "gf" | "gs"))
+                    && style["hd"].as_bool() != Some(true)
+            })
+            .chain(inherited_styles.iter().copied())
+            .collect();
+
+        if item["ty"] == "gr" {
+            if let Some(group_items) = item["it"].as_array() {
+                draw_shapes(pixmap, group_items, &styles, frame, transform, opacity);
+            }
+
+            continue;
+        }
+
+        let Some(path) = shape_path(item, frame) else {
+            continue;
+        };
+
+        // The nearest style is drawn last, on top of the others.
+        for style in styles.iter().rev() {
+            paint_path(pixmap, &path, style, frame, transform, opacity);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    const RED: [u8; 4] = [255, 0, 0, 255];
+    const BLUE: [u8; 4] = [0, 0, 255, 255];
+    const CLEAR: [u8; 4] = [0, 0, 0, 0];
+
+    fn animation(layers: Value, assets: Value) -> Animation {
+        let data =
+            json!({ "w": 100, "h": 100, "ip": 0, "op": 10, "layers": layers, "assets": assets });
+
+        Animation::parse(&serde_json::to_vec(&data).unwrap()).unwrap()
+    }
+
+    fn shape_layer(shape: Value, color: [f64; 3]) -> Value {
+        json!({
+            "ty": 4,
+            "ks": {},
+            "shapes": [shape, { "ty": "fl", "c": { "k": color }, "o": { "k": 100 } }],
+        })
+    }
+
+    fn pixel(image: &RgbaImage, x: u32, y: u32) -> [u8; 4] {
+        image.get_pixel(x, y).0
+    }
+
+    #[test]
+    fn rect_fills_its_bounds() {
+        let rect =
+            json!({ "ty": "rc", "p": { "k": [50, 50] }, "s": { "k": [40, 20] }, "r": { "k": 0 } });
+        let animation = animation(json!([shape_layer(rect, [1.0, 0.0, 0.0])]), json!([]));
+        let frame = animation.render(0).unwrap();
+
+        assert_eq!(animation.frame_count(), 10);
+        assert_eq!(pixel(&frame, 50, 50), RED);
+        assert_eq!(pixel(&frame, 32, 42), RED);
+        assert_eq!(pixel(&frame, 50, 30), CLEAR);
+        assert_eq!(pixel(&frame, 20, 50), CLEAR);
+    }
+
+    #[test]
+    fn ellipse_leaves_its_corners_empty() {
+        let ellipse = json!({ "ty": "el", "p": { "k": [50, 50] }, "s": { "k": [40, 40] } });
+        let animation = animation(json!([shape_layer(ellipse, [1.0, 0.0, 0.0])]), json!([]));
+        let frame = animation.render(0).unwrap();
+
+        assert_eq!(pixel(&frame, 50, 50), RED);
+        assert_eq!(pixel(&frame, 50, 33), RED);
+        assert_eq!(pixel(&frame, 33, 33), CLEAR);
+    }
+
+    #[test]
+    fn precomp_follows_its_parent() {
+        let rect =
+            json!({ "ty": "rc", "p": { "k": [10, 10] }, "s": { "k": [10, 10] }, "r": { "k": 0 } });
+        let assets = json!([{ "id": "comp", "layers": [shape_layer(rect, [0.0, 0.0, 1.0])] }]);
+        let layers = json!([
+            { "ty": 3, "ind": 1, "ks": { "p": { "k": [40, 0] } } },
+            { "ty": 0, "ind": 2, "parent": 1, "refId": "comp", "ks": { "p": { "k": [0, 30] } } },
+        ]);
+        let frame = animation(layers, assets).render(0).unwrap();
+
+        assert_eq!(pixel(&frame, 50, 40), BLUE);
+        assert_eq!(pixel(&frame, 10, 10), CLEAR);
+        assert_eq!(pixel(&frame, 50, 10), CLEAR);
+    }
+
+    #[test]
+    fn keyframes_are_interpolated() {
+        let rect = json!({
+            "ty": "rc",
+            "p": { "a": 1, "k": [{ "t": 0, "s": [20, 50] }, { "t": 10, "s": [80, 50] }] },
+            "s": { "k": [10, 10] },
+            "r": { "k": 0 },
+        });
+        let animation = animation(json!([shape_layer(rect, [1.0, 0.0, 0.0])]), json!([]));
+
+        let first = animation.render(0).unwrap();
+        assert_eq!(pixel(&first, 20, 50), RED);
+        assert_eq!(pixel(&first, 50, 50), CLEAR);
+
+        let middle = animation.render(5).unwrap();
+        assert_eq!(pixel(&middle, 20, 50), CLEAR);
+        assert_eq!(pixel(&middle, 50, 50), RED);
+    }
+}
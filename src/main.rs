@@ -1,18 +1,30 @@
-#![feature(slice_pattern)]
-
+mod assets;
+mod barcode;
+mod cache;
+mod config;
+mod highlight;
+mod imaging;
+mod layout;
+mod lottie;
+mod prefetch;
+mod raster;
 mod renderer;
 mod socket;
+mod style;
 
-use core::slice::SlicePattern;
-use std::{sync::mpsc, thread};
+use std::{fmt, sync::mpsc, thread};
 
+use barcode::Barcode;
+use base64::{engine::general_purpose::STANDARD, Engine};
 use dotenvy_macro::dotenv;
 use escpos::{
     driver::UsbDriver,
     printer::Printer,
     printer_options::PrinterOptions,
-    utils::{DebugMode, Protocol, ESC},
+    utils::{DebugMode, Protocol, QRCodeCorrectionLevel, QRCodeModel, QRCodeOption, ESC},
 };
+use imaging::{Bitmap, ImageOverrides, ImageSettings};
+use raster::{RasterText, TextStyle};
 use renderer::print_message;
 use serde::{Deserialize, Serialize};
 use serenity::{
@@ -23,38 +35,276 @@ use serenity::{
     Client,
 };
 use socket::APISocket;
+use tokio::sync::mpsc::{unbounded_channel, UnboundedSender};
 use twemoji_assets::png::PngTwemojiAsset;
 
-#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
 pub enum UnderlineMode {
+    #[default]
     None,
     Single,
     Double,
 }
 
-#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
 pub enum JustifyMode {
+    #[default]
     Left,
     Center,
     Right,
 }
 
+/// The printer's built-in fonts. Font B is smaller and fits more per line.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub enum Font {
+    #[default]
+    A,
+    B,
+}
+
+impl Font {
+    pub fn chars_per_line(self) -> u8 {
+        match self {
+            Font::A => CHARS_PER_LINE,
+            Font::B => 64,
+        }
+    }
+}
+
+/// `Native` prints text with the printer's own fonts and code pages. `Raster`
+/// renders it with TrueType fonts into bit images, which gives real italics
+/// and covers what the bundled DejaVu fonts do: Latin, Greek, Cyrillic and
+/// most symbols. CJK needs a font in `fonts/fallback`. Each character gets a
+/// cell of its own in the order it was written, so scripts that need shaping
+/// or run right to left, like Arabic, don't print readably.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub enum TextMode {
+    #[default]
+    Native,
+    Raster,
+}
+
+/// How much of a QR code can be damaged and still scan: about 7%, 15%, 25%
+/// or 30%. Higher levels make denser codes.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub enum QrCorrection {
+    L,
+    #[default]
+    M,
+    Q,
+    H,
+}
+
+fn default_qr_size() -> u8 {
+    4
+}
+
+/// A QR code encoding `data`. `size` is the width of one module in dots,
+/// from 1 to 16.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct QrCode {
+    pub data: String,
+    #[serde(default = "default_qr_size")]
+    pub size: u8,
+    #[serde(default)]
+    pub correction: QrCorrection,
+}
+
+/// Where an image's bytes come from. `Binary` names a websocket binary frame
+/// by the SHA-256 of its contents, and is swapped for `Data` by the socket.
+#[derive(Clone)]
+pub enum ImageSource {
+    Url(String),
+    Data(Vec<u8>),
+    Binary(String),
+}
+
+impl fmt::Debug for ImageSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ImageSource::Url(url) => write!(f, "Url({:?})", url),
+            ImageSource::Data(data) => write!(f, "Data({} bytes)", data.len()),
+            ImageSource::Binary(id) => write!(f, "Binary({:?})", id),
+        }
+    }
+}
+
+/// An image to print. The API accepts a bare URL string, or an object with
+/// one of `url`, `data` (base64) or `binary`, plus optional settings.
+/// `fallback` is printed as text if the image can't be fetched or decoded.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(try_from = "ImageRepr", into = "ImageRepr")]
+pub struct Image {
+    pub source: ImageSource,
+    pub settings: ImageOverrides,
+    pub fallback: Option<String>,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(untagged)]
+enum ImageRepr {
+    Url(String),
+    Full {
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        url: Option<String>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        data: Option<String>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        binary: Option<String>,
+        #[serde(default)]
+        settings: ImageOverrides,
+        #[serde(default)]
+        fallback: Option<String>,
+    },
+}
+
+impl TryFrom<ImageRepr> for Image {
+    type Error = String;
+
+    fn try_from(repr: ImageRepr) -> Result<Self, Self::Error> {
+        match repr {
+            ImageRepr::Url(url) => Ok(Image::from(url)),
+            ImageRepr::Full {
+                url,
+                data,
+                binary,
+                settings,
+                fallback,
+            } => {
+                let source = match (url, data, binary) {
+                    (Some(url), None, None) => ImageSource::Url(url),
+                    (None, Some(data), None) => {
+                        // Accept data URLs as well as bare base64.
+                        let data = match data.split_once(";base64,") {
+                            Some((_, data)) => data.to_string(),
+                            None => data,
+                        };
+
+                        ImageSource::Data(
+                            STANDARD
+                                .decode(data.trim())
+                                .map_err(|error| format!("invalid image data: {}", error))?,
+                        )
+                    }
+                    (None, None, Some(binary)) => ImageSource::Binary(binary.to_lowercase()),
+                    _ => {
+                        return Err("an image needs exactly one of url, data or binary".to_string());
+                    }
+                };
+
+                Ok(Image {
+                    source,
+                    settings,
+                    fallback,
+                })
+            }
+        }
+    }
+}
+
+impl From<Image> for ImageRepr {
+    fn from(image: Image) -> Self {
+        let (url, data, binary) = match image.source {
+            ImageSource::Url(url) => (Some(url), None, None),
+            ImageSource::Data(data) => (None, Some(STANDARD.encode(data)), None),
+            ImageSource::Binary(binary) => (None, None, Some(binary)),
+        };
+
+        ImageRepr::Full {
+            url,
+            data,
+            binary,
+            settings: image.settings,
+            fallback: image.fallback,
+        }
+    }
+}
+
+impl From<String> for Image {
+    fn from(url: String) -> Self {
+        Image::from(ImageSource::Url(url))
+    }
+}
+
+impl From<ImageSource> for Image {
+    fn from(source: ImageSource) -> Self {
+        Image {
+            source,
+            settings: ImageOverrides::default(),
+            fallback: None,
+        }
+    }
+}
+
+impl Image {
+    pub fn with_settings(self, settings: ImageOverrides) -> Self {
+        Image { settings, ..self }
+    }
+
+    pub fn with_fallback(self, fallback: String) -> Self {
+        Image {
+            fallback: Some(fallback),
+            ..self
+        }
+    }
+}
+
 #[derive(Clone, Debug, Serialize, Deserialize)]
 #[serde(tag = "type", content = "content")]
 pub enum PrinterInstruction {
     Text(String),
-    Image(String),
+    Image(Image),
+    /// An image already downloaded and processed by the prefetch stage.
+    #[serde(skip)]
+    Bitmap(Bitmap),
+    /// Defaults for the images that follow; each `Image` can override them.
+    ImageSettings(ImageSettings),
     Reverse(bool),
     Underline(UnderlineMode),
     Justify(JustifyMode),
     Strike(bool),
     Bold(bool),
     Italic(bool),
+    TextMode(TextMode),
+    /// Starts a block whose lines begin with `first_line` and then `hanging`,
+    /// e.g. a list bullet followed by spaces. Blocks nest until `Dedent`.
+    Indent {
+        first_line: String,
+        hanging: String,
+    },
+    Dedent,
+    /// Prints the following lines rotated 180 degrees, in reverse order, so
+    /// they read normally with the receipt turned around.
+    UpsideDown(bool),
+    /// Character width and height multipliers, from 1 to 8. Like `Font`, this
+    /// only applies to native text.
+    Size {
+        width: u8,
+        height: u8,
+    },
+    Font(Font),
+    /// Line spacing in dots, or the printer's default for `None`.
+    LineSpacing(Option<u8>),
+    QrCode(QrCode),
+    Barcode(Barcode),
     PrintCut,
 }
 
 pub type PrinterMessage = Vec<PrinterInstruction>;
 
+/// Where a job came from, which decides the limits it is printed under.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum JobSource {
+    Discord,
+    Api,
+}
+
+#[derive(Debug)]
+pub struct PrintJob {
+    pub source: JobSource,
+    pub instructions: PrinterMessage,
+}
+
 struct Handler;
 
 #[async_trait]
@@ -94,14 +344,27 @@ impl EventHandler for Handler {
 struct PrinterChannel;
 
 impl TypeMapKey for PrinterChannel {
-    type Value = mpsc::Sender<PrinterMessage>;
+    type Value = UnboundedSender<PrintJob>;
 }
 
 pub const CHARS_PER_LINE: u8 = 48;
+pub const DOTS_PER_LINE: u32 = 576;
+pub const DOTS_PER_MM: u32 = 8;
+
+fn print_raster_text(printer: &mut Printer<UsbDriver>, raster_text: &mut RasterText) {
+    let Some(png_data) = raster_text.render() else {
+        return;
+    };
+
+    let _ = printer.bit_image_from_bytes(&png_data);
+}
 
 #[tokio::main]
 async fn main() {
-    let (sender, receiver) = mpsc::channel::<PrinterMessage>();
+    let (sender, receiver) = mpsc::channel::<PrintJob>();
+    let (job_sender, job_receiver) = unbounded_channel::<PrintJob>();
+
+    tokio::spawn(prefetch::run(job_receiver, sender));
 
     thread::spawn(move || {
         let driver = match UsbDriver::open(0x04B8, 0x0E20, None) {
@@ -125,10 +388,12 @@ async fn main() {
         //let emoji_regex = Regex::new(r"(\xC2\xA9|\xC2\AE|[\xE2\x80\x80-\xE3\x8C\x8C]|\xED\xA0\xBE[\x80\x80-\xBF\xBF]|\ud83d[\ud000-\udfff]|\ud83e[\ud000-\udfff])").unwrap();
 
         loop {
-            let Ok(messages) = receiver.recv() else {
+            let Ok(job) = receiver.recv() else {
                 continue;
             };
 
+            let messages = job.instructions;
+
             println!("printer commands: {:?}", messages);
 
             let _ = printer.reverse(false);
@@ -137,6 +402,14 @@ async fn main() {
             let _ = printer.double_strike(false);
             let _ = printer.bold(false);
             let _ = printer.custom(&[ESC, 0x35]);
+            let _ = printer.reset_size();
+            let _ = printer.font(escpos::utils::Font::A);
+            let _ = printer.reset_line_spacing();
+            let _ = printer.custom(&[ESC, b'{', 0]);
+
+            let mut text_mode = TextMode::Native;
+            let mut style = TextStyle::default();
+            let mut raster_text = RasterText::new();
 
             let mut last_command_was_print = false;
 
@@ -195,6 +468,11 @@ async fn main() {
                                 i += 4;
                             }
 
+                            if text_mode == TextMode::Raster {
+                                raster_text.write(&curr_char, style);
+                                continue;
+                            }
+
                             let Some(png_asset) = PngTwemojiAsset::from_emoji(&curr_char) else {
                                 let _ = printer.write(&curr_char);
                                 continue;
@@ -205,22 +483,20 @@ async fn main() {
                             let _ = printer.bit_image_from_bytes(png_data);
                         }
                     }
-                    PrinterInstruction::Image(url) => {
-                        let Ok(image) = reqwest::blocking::get(url) else {
-                            continue;
-                        };
-
-                        let Ok(image) = image.bytes() else {
-                            continue;
-                        };
+                    PrinterInstruction::Bitmap(bitmap) => {
+                        print_raster_text(&mut printer, &mut raster_text);
 
                         let _ = printer.feed();
-                        let _ = printer.bit_image_from_bytes(image.as_slice());
+                        let _ = printer.bit_image_from_bytes(&bitmap.0);
                     }
+                    // Resolved into bitmaps before jobs reach the printer.
+                    PrinterInstruction::Image(_) | PrinterInstruction::ImageSettings(_) => {}
                     PrinterInstruction::Reverse(enabled) => {
+                        style.reverse = enabled;
                         let _ = printer.reverse(enabled);
                     }
                     PrinterInstruction::Underline(mode) => {
+                        style.underline = mode;
                         let _ = printer.underline(match mode {
                             UnderlineMode::None => escpos::utils::UnderlineMode::None,
                             UnderlineMode::Single => escpos::utils::UnderlineMode::Single,
@@ -228,6 +504,7 @@ async fn main() {
                         });
                     }
                     PrinterInstruction::Justify(mode) => {
+                        style.justify = mode;
                         let _ = printer.justify(match mode {
                             JustifyMode::Left => escpos::utils::JustifyMode::LEFT,
                             JustifyMode::Center => escpos::utils::JustifyMode::CENTER,
@@ -235,20 +512,78 @@ async fn main() {
                         });
                     }
                     PrinterInstruction::Strike(enabled) => {
+                        style.strike = enabled;
                         let _ = printer.double_strike(enabled);
                     }
                     PrinterInstruction::Bold(enabled) => {
+                        style.bold = enabled;
                         let _ = printer.bold(enabled);
                     }
-                    PrinterInstruction::Italic(enabled) => match enabled {
-                        true => {
-                            let _ = printer.custom(&[ESC, 0x34]);
+                    PrinterInstruction::Italic(enabled) => {
+                        style.italic = enabled;
+
+                        match enabled {
+                            true => {
+                                let _ = printer.custom(&[ESC, 0x34]);
+                            }
+                            false => {
+                                let _ = printer.custom(&[ESC, 0x35]);
+                            }
+                        }
+                    }
+                    PrinterInstruction::TextMode(mode) => {
+                        print_raster_text(&mut printer, &mut raster_text);
+                        text_mode = mode;
+                    }
+                    PrinterInstruction::Indent { .. } | PrinterInstruction::Dedent => {}
+                    PrinterInstruction::UpsideDown(enabled) => {
+                        print_raster_text(&mut printer, &mut raster_text);
+                        style.upside_down = enabled;
+                        let _ = printer.custom(&[ESC, b'{', enabled as u8]);
+                    }
+                    PrinterInstruction::Size { width, height } => {
+                        let _ = printer.size(width.clamp(1, 8), height.clamp(1, 8));
+                    }
+                    PrinterInstruction::Font(font) => {
+                        let _ = printer.font(match font {
+                            Font::A => escpos::utils::Font::A,
+                            Font::B => escpos::utils::Font::B,
+                        });
+                    }
+                    PrinterInstruction::LineSpacing(spacing) => match spacing {
+                        Some(spacing) => {
+                            let _ = printer.line_spacing(spacing);
                         }
-                        false => {
-                            let _ = printer.custom(&[ESC, 0x35]);
+                        None => {
+                            let _ = printer.reset_line_spacing();
                         }
                     },
+                    PrinterInstruction::QrCode(qr_code) => {
+                        print_raster_text(&mut printer, &mut raster_text);
+
+                        let _ = printer.feed();
+                        let _ = printer.qrcode_option(
+                            &qr_code.data,
+                            QRCodeOption::new(
+                                QRCodeModel::Model2,
+                                qr_code.size.clamp(1, 16),
+                                match qr_code.correction {
+                                    QrCorrection::L => QRCodeCorrectionLevel::L,
+                                    QrCorrection::M => QRCodeCorrectionLevel::M,
+                                    QrCorrection::Q => QRCodeCorrectionLevel::Q,
+                                    QrCorrection::H => QRCodeCorrectionLevel::H,
+                                },
+                            ),
+                        );
+                    }
+                    PrinterInstruction::Barcode(barcode) => {
+                        print_raster_text(&mut printer, &mut raster_text);
+
+                        let _ = printer.feed();
+                        let _ = printer.custom(&barcode.commands());
+                    }
                     PrinterInstruction::PrintCut => {
+                        print_raster_text(&mut printer, &mut raster_text);
                         let _ = printer.feed();
                         let _ = printer.partial_cut();
                         let _ = printer.print();
@@ -258,6 +593,8 @@ async fn main() {
                 };
             }
 
+            print_raster_text(&mut printer, &mut raster_text);
+
             if !last_command_was_print {
                 let _ = printer.feed();
                 let _ = printer.partial_cut();
@@ -267,7 +604,7 @@ async fn main() {
         }
     });
 
-    let api_job_sender = sender.clone();
+    let api_job_sender = job_sender.clone();
     let (mut api_socket, api_receiver) = APISocket::create();
 
     let _ = thread::spawn(move || loop {
@@ -277,7 +614,10 @@ async fn main() {
 
         println!("from the API: {:?}", next_message);
 
-        let _ = api_job_sender.send(next_message);
+        let _ = api_job_sender.send(PrintJob {
+            source: JobSource::Api,
+            instructions: next_message,
+        });
     });
 
     let _ = thread::spawn(move || api_socket.run());
@@ -287,7 +627,7 @@ async fn main() {
         | GatewayIntents::MESSAGE_CONTENT;
 
     let mut printer_map = TypeMap::new();
-    printer_map.insert::<PrinterChannel>(sender);
+    printer_map.insert::<PrinterChannel>(job_sender);
 
     let mut client = Client::builder(dotenv!("BOT_TOKEN"), intents)
         .event_handler(Handler)
@@ -0,0 +1,122 @@
+use std::{collections::HashMap, sync::mpsc};
+
+use tokio::{sync::mpsc::UnboundedReceiver, task::JoinSet};
+
+use crate::{
+    assets::{self, FetchError},
+    cache::{self, Entry},
+    config::{ImageLimits, CONFIG},
+    imaging::{self, Bitmap, ImageSettings, ProcessError},
+    layout::layout,
+    style::normalize,
+    ImageSource, JobSource, PrintJob, PrinterInstruction, PrinterMessage,
+};
+
+const OVER_LIMIT_NOTICE: &str = "\n[image too large to print]\n";
+
+async fn resolve(
+    source: ImageSource,
+    settings: ImageSettings,
+    limits: ImageLimits,
+) -> Option<PrinterInstruction> {
+    let data = match source {
+        ImageSource::Url(url) => match assets::fetch(&url, &limits).await {
+            Ok(data) => data,
+            Err(FetchError::TooLarge) => {
+                return Some(PrinterInstruction::Text(OVER_LIMIT_NOTICE.to_owned()));
+            }
+            Err(_) => return None,
+        },
+        ImageSource::Data(data) if data.len() as u64 > limits.max_download_bytes => {
+            return Some(PrinterInstruction::Text(OVER_LIMIT_NOTICE.to_owned()));
+        }
+        ImageSource::Data(data) => data,
+        // A binary frame the socket never received.
+        ImageSource::Binary(_) => return None,
+    };
+
+    let raster_key = cache::key(&[&data, format!("{:?}{:?}", settings, limits).as_bytes()]);
+
+    if let Some(png_data) = cache::load(Entry::Raster, raster_key.clone()).await {
+        return Some(PrinterInstruction::Bitmap(Bitmap(png_data)));
+    }
+
+    let processed =
+        tokio::task::spawn_blocking(move || imaging::process(&data, &settings, &limits))
+            .await
+            .ok()?;
+
+    match processed {
+        Ok(png_data) => {
+            cache::store(Entry::Raster, raster_key, png_data.clone()).await;
+
+            Some(PrinterInstruction::Bitmap(Bitmap(png_data)))
+        }
+        Err(ProcessError::OverLimit) => {
+            Some(PrinterInstruction::Text(OVER_LIMIT_NOTICE.to_owned()))
+        }
+        Err(_) => None,
+    }
+}
+
+/// Downloads and processes every image in the job at once, replacing each
+/// `Image` with the `Bitmap` to print. Images that fail are replaced with
+/// their fallback text, if they have one.
+async fn materialize(source: JobSource, instructions: PrinterMessage) -> PrinterMessage {
+    let limits = CONFIG.limits(source);
+    let mut image_settings = ImageSettings::default();
+    let mut downloads = JoinSet::new();
+
+    for (index, instruction) in instructions.iter().enumerate() {
+        match instruction {
+            PrinterInstruction::ImageSettings(settings) => {
+                image_settings = *settings;
+            }
+            PrinterInstruction::Image(image) => {
+                let download = resolve(
+                    image.source.clone(),
+                    image_settings.with(&image.settings),
+                    limits.clone(),
+                );
+
+                downloads.spawn(async move { (index, download.await) });
+            }
+            _ => {}
+        }
+    }
+
+    let mut resolved = HashMap::new();
+
+    while let Some(result) = downloads.join_next().await {
+        let Ok((index, instruction)) = result else {
+            continue;
+        };
+
+        resolved.insert(index, instruction);
+    }
+
+    instructions
+        .into_iter()
+        .enumerate()
+        .filter_map(|(index, instruction)| match instruction {
+            PrinterInstruction::Image(image) => resolved
+                .remove(&index)
+                .flatten()
+                .or_else(|| image.fallback.map(PrinterInstruction::Text)),
+            instruction => Some(instruction),
+        })
+        .collect()
+}
+
+/// Prepares jobs off the printer thread, so the worker only ever receives
+/// jobs it can print straight through.
+pub async fn run(mut jobs: UnboundedReceiver<PrintJob>, printer: mpsc::Sender<PrintJob>) {
+    while let Some(job) = jobs.recv().await {
+        let instructions = materialize(job.source, normalize(job.instructions)).await;
+
+        let _ = printer.send(PrintJob {
+            source: job.source,
+            instructions: layout(instructions),
+        });
+    }
+}
@@ -0,0 +1,359 @@
+use std::{fs, io::Cursor};
+
+use ab_glyph::{point, Font, FontArc, PxScale, ScaleFont};
+use image::{
+    imageops::{self, FilterType},
+    GrayImage, ImageFormat, Luma,
+};
+use lazy_static::lazy_static;
+use twemoji_assets::png::PngTwemojiAsset;
+
+use crate::{JustifyMode, UnderlineMode, DOTS_PER_LINE};
+
+/// Raster text sits on the same 12x24 dot grid as the printer's built-in
+/// Font A, so both modes fit `CHARS_PER_LINE` columns on the paper.
+const CELL_WIDTH: f32 = 12.0;
+const LINE_HEIGHT: u32 = 24;
+
+/// Extra `.ttf`/`.otf` files dropped in here are tried after the bundled
+/// fonts, for scripts that DejaVu doesn't cover. Nothing is bundled for CJK,
+/// so it prints as empty boxes until a font like Noto Sans CJK is added.
+const FALLBACK_FONT_DIR: &str = "fonts/fallback";
+
+struct Fonts {
+    /// `PxScale` sets the ascent to descent height rather than the em size,
+    /// so the scale that makes a monospace advance `CELL_WIDTH` dots wide is
+    /// worked out from the font itself.
+    scale: PxScale,
+    regular: FontArc,
+    bold: FontArc,
+    italic: FontArc,
+    bold_italic: FontArc,
+    fallback: Vec<FontArc>,
+}
+
+impl Fonts {
+    fn load() -> Fonts {
+        let mut fallback =
+            vec![FontArc::try_from_slice(include_bytes!("../fonts/DejaVuSans.ttf")).unwrap()];
+
+        if let Ok(entries) = fs::read_dir(FALLBACK_FONT_DIR) {
+            for entry in entries.flatten() {
+                let Ok(data) = fs::read(entry.path()) else {
+                    continue;
+                };
+
+                let Ok(font) = FontArc::try_from_vec(data) else {
+                    continue;
+                };
+
+                fallback.push(font);
+            }
+        }
+
+        let regular =
+            FontArc::try_from_slice(include_bytes!("../fonts/DejaVuSansMono.ttf")).unwrap();
+        let advance = regular.h_advance_unscaled(regular.glyph_id('0'));
+
+        Fonts {
+            scale: PxScale::from(CELL_WIDTH * regular.height_unscaled() / advance),
+            regular,
+            bold: FontArc::try_from_slice(include_bytes!("../fonts/DejaVuSansMono-Bold.ttf"))
+                .unwrap(),
+            italic: FontArc::try_from_slice(include_bytes!("../fonts/DejaVuSansMono-Oblique.ttf"))
+                .unwrap(),
+            bold_italic: FontArc::try_from_slice(include_bytes!(
+                "../fonts/DejaVuSansMono-BoldOblique.ttf"
+            ))
+            .unwrap(),
+            fallback,
+        }
+    }
+
+    fn for_char(&self, c: char, style: &TextStyle) -> &FontArc {
+        let styled = match (style.bold, style.italic) {
+            (false, false) => &self.regular,
+            (true, false) => &self.bold,
+            (false, true) => &self.italic,
+            (true, true) => &self.bold_italic,
+        };
+
+        if styled.glyph_id(c).0 != 0 {
+            return styled;
+        }
+
+        self.fallback
+            .iter()
+            .find(|font| font.glyph_id(c).0 != 0)
+            .unwrap_or(styled)
+    }
+}
+
+lazy_static! {
+    static ref FONTS: Fonts = Fonts::load();
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct TextStyle {
+    pub bold: bool,
+    pub italic: bool,
+    pub underline: UnderlineMode,
+    pub strike: bool,
+    pub reverse: bool,
+    pub justify: JustifyMode,
+    pub upside_down: bool,
+}
+
+#[derive(Clone)]
+enum Cell {
+    Glyph(char, TextStyle),
+    Emoji(&'static [u8], TextStyle),
+}
+
+impl Cell {
+    fn style(&self) -> &TextStyle {
+        match self {
+            Cell::Glyph(_, style) => style,
+            Cell::Emoji(_, style) => style,
+        }
+    }
+
+    fn advance(&self) -> f32 {
+        match self {
+            Cell::Glyph(c, style) => {
+                let font = FONTS.for_char(*c, style).as_scaled(FONTS.scale);
+
+                font.h_advance(font.glyph_id(*c))
+            }
+            Cell::Emoji(_, _) => LINE_HEIGHT as f32,
+        }
+    }
+
+    fn is_space(&self) -> bool {
+        matches!(self, Cell::Glyph(c, _) if c.is_whitespace())
+    }
+}
+
+/// Buffers styled text and renders it with TrueType fonts into a bit image
+/// as wide as the paper, for text the printer's code pages can't express.
+#[derive(Default)]
+pub struct RasterText {
+    cells: Vec<Cell>,
+}
+
+impl RasterText {
+    pub fn new() -> RasterText {
+        RasterText::default()
+    }
+
+    pub fn write(&mut self, text: &str, style: TextStyle) {
+        if let Some(png_asset) = PngTwemojiAsset::from_emoji(text) {
+            self.cells.push(Cell::Emoji(png_asset, style));
+            return;
+        }
+
+        for c in text.chars() {
+            self.cells.push(Cell::Glyph(c, style));
+        }
+    }
+
+    fn lines(&self) -> Vec<Vec<(Cell, f32)>> {
+        let mut lines = Vec::new();
+        let mut line: Vec<(Cell, f32)> = Vec::new();
+        let mut line_width = 0.0;
+
+        for cell in &self.cells {
+            if let Cell::Glyph('\n', _) = cell {
+                lines.push(line);
+                line = Vec::new();
+                line_width = 0.0;
+                continue;
+            }
+
+            let advance = cell.advance();
+
+            if line_width + advance > DOTS_PER_LINE as f32 && !line.is_empty() {
+                let rest = match line.iter().rposition(|(cell, _)| cell.is_space()) {
+                    Some(space) => line.split_off(space + 1),
+                    None => Vec::new(),
+                };
+
+                lines.push(line);
+                line = rest;
+                line_width = line.iter().map(|(_, advance)| advance).sum();
+
+                if line.is_empty() && cell.is_space() {
+                    continue;
+                }
+            }
+
+            line.push((cell.clone(), advance));
+            line_width += advance;
+        }
+
+        if !line.is_empty() {
+            lines.push(line);
+        }
+
+        lines
+    }
+
+    /// Renders everything written since the last call as a PNG, or `None` if
+    /// nothing was written.
+    pub fn render(&mut self) -> Option<Vec<u8>> {
+        if self.cells.is_empty() {
+            return None;
+        }
+
+        let mut lines = self.lines();
+        let upside_down = self
+            .cells
+            .first()
+            .is_some_and(|cell| cell.style().upside_down);
+
+        self.cells.clear();
+
+        if lines.is_empty() {
+            lines.push(Vec::new());
+        }
+
+        let mut canvas =
+            GrayImage::from_pixel(DOTS_PER_LINE, LINE_HEIGHT * lines.len() as u32, Luma([255]));
+
+        for (line_index, line) in lines.iter().enumerate() {
+            let Some((first_cell, _)) = line.first() else {
+                continue;
+            };
+
+            let text_width: f32 = line
+                .iter()
+                .rev()
+                .skip_while(|(cell, _)| cell.is_space())
+                .map(|(_, advance)| advance)
+                .sum();
+
+            let mut x = match first_cell.style().justify {
+                JustifyMode::Left => 0.0,
+                JustifyMode::Center => (DOTS_PER_LINE as f32 - text_width).max(0.0) / 2.0,
+                JustifyMode::Right => (DOTS_PER_LINE as f32 - text_width).max(0.0),
+            };
+
+            let top = LINE_HEIGHT * line_index as u32;
+
+            for (cell, advance) in line {
+                draw_cell(&mut canvas, cell, x, top, *advance);
+                x += advance;
+            }
+        }
+
+        // The worker flushes whenever upside down mode changes, so it applies
+        // to the whole image.
+        if upside_down {
+            imageops::rotate180_in_place(&mut canvas);
+        }
+
+        let mut png_data = Vec::new();
+
+        image::DynamicImage::ImageLuma8(canvas)
+            .write_to(&mut Cursor::new(&mut png_data), ImageFormat::Png)
+            .ok()?;
+
+        Some(png_data)
+    }
+}
+
+fn put_pixel(canvas: &mut GrayImage, x: i32, y: i32, ink: Luma<u8>) {
+    if x < 0 || y < 0 || x >= canvas.width() as i32 || y >= canvas.height() as i32 {
+        return;
+    }
+
+    canvas.put_pixel(x as u32, y as u32, ink);
+}
+
+fn draw_cell(canvas: &mut GrayImage, cell: &Cell, x: f32, top: u32, advance: f32) {
+    let style = cell.style();
+    let left = x.round() as i32;
+    let right = (x + advance).round() as i32;
+    let top = top as i32;
+
+    let (ink, paper) = match style.reverse {
+        true => (Luma([255]), Luma([0])),
+        false => (Luma([0]), Luma([255])),
+    };
+
+    if style.reverse {
+        for py in top..top + LINE_HEIGHT as i32 {
+            for px in left..right {
+                put_pixel(canvas, px, py, paper);
+            }
+        }
+    }
+
+    let font = FONTS.regular.as_scaled(FONTS.scale);
+    let baseline = top as f32 + font.ascent();
+
+    match cell {
+        Cell::Glyph(c, style) => {
+            let font = FONTS.for_char(*c, style);
+            let glyph = font.as_scaled(FONTS.scale).scaled_glyph(*c);
+            let glyph = ab_glyph::Glyph {
+                position: point(x, baseline),
+                ..glyph
+            };
+
+            if let Some(outline) = font.outline_glyph(glyph) {
+                let bounds = outline.px_bounds();
+
+                outline.draw(|gx, gy, coverage| {
+                    if coverage < 0.5 {
+                        return;
+                    }
+
+                    put_pixel(
+                        canvas,
+                        bounds.min.x as i32 + gx as i32,
+                        bounds.min.y as i32 + gy as i32,
+                        ink,
+                    );
+                });
+            }
+        }
+        Cell::Emoji(png_data, _) => {
+            let Ok(emoji) = image::load_from_memory(png_data) else {
+                return;
+            };
+
+            let emoji = emoji
+                .resize(LINE_HEIGHT, LINE_HEIGHT, FilterType::Triangle)
+                .to_luma_alpha8();
+
+            for (ex, ey, pixel) in emoji.enumerate_pixels() {
+                let [luma, alpha] = pixel.0;
+
+                if alpha < 128 || luma > 160 {
+                    continue;
+                }
+
+                put_pixel(canvas, left + ex as i32, top + ey as i32, ink);
+            }
+        }
+    }
+
+    let underline_rows = match style.underline {
+        UnderlineMode::None => 0,
+        UnderlineMode::Single => 1,
+        UnderlineMode::Double => 2,
+    };
+
+    for row in 0..underline_rows {
+        for px in left..right {
+            put_pixel(canvas, px, baseline as i32 + 2 + row, ink);
+        }
+    }
+
+    if style.strike {
+        for px in left..right {
+            put_pixel(canvas, px, top + LINE_HEIGHT as i32 / 2, ink);
+        }
+    }
+}
@@ -1,21 +1,541 @@
 use discord_markdown::parser::{parse, Expression};
 use regex::Regex;
+use reqwest::Url;
 use serenity::all::{
-    ArgumentConvert, Channel, Context, Message, Role, User,
+    ArgumentConvert, Attachment, Channel, Context, Embed, Message, Role, StickerFormatType, User,
 };
-use std::sync::mpsc;
+use tokio::sync::mpsc::UnboundedSender;
+use twemoji_assets::png::PngTwemojiAsset;
 
-use crate::{PrinterInstruction, PrinterMessage, UnderlineMode, CHARS_PER_LINE};
+use crate::{
+    assets,
+    config::{
+        CodeConfig, CollageConfig, ImageLimits, QrCodeConfig, SpoilerMode, TextAttachmentConfig,
+        CONFIG,
+    },
+    highlight::{self, TokenKind},
+    imaging::{self, AnimationMode, ImageOverrides, ImageSettings},
+    layout::{display_width, split_columns},
+    Font, Image, ImageSource, JobSource, JustifyMode, PrintJob, PrinterInstruction, PrinterMessage,
+    QrCode, TextMode, UnderlineMode, CHARS_PER_LINE,
+};
+
+/// What the message body collects for the end of the receipt. `footnotes`
+/// holds the targets of masked links and `spoilers` the folded away spoilers,
+/// both numbered from 1. A spoiler gets a footer of its own, so its links
+/// stay hidden with it.
+#[derive(Default)]
+struct Footer {
+    links: Vec<String>,
+    footnotes: Vec<String>,
+    spoilers: Vec<PrinterMessage>,
+    inside_spoiler: bool,
+}
+
+impl Footer {
+    fn spoiler() -> Footer {
+        Footer {
+            inside_spoiler: true,
+            ..Footer::default()
+        }
+    }
+
+    /// Starts the next folded spoiler, behind the number its marker shows.
+    fn next_spoiler(&self) -> PrinterMessage {
+        let marker = format!("{}. ", self.spoilers.len() + 1);
+
+        vec![PrinterInstruction::Indent {
+            hanging: " ".repeat(marker.len()),
+            first_line: marker,
+        }]
+    }
+
+    /// Adds a spoiler started with `next_spoiler` and returns its number.
+    fn fold(&mut self, mut spoiler: PrinterMessage) -> usize {
+        spoiler.push(PrinterInstruction::Dedent);
+        self.spoilers.push(spoiler);
+        self.spoilers.len()
+    }
+
+    fn footnote(&mut self, url: &str) -> usize {
+        match self.footnotes.iter().position(|footnote| footnote == url) {
+            Some(index) => index + 1,
+            None => {
+                self.footnotes.push(url.to_string());
+                self.footnotes.len()
+            }
+        }
+    }
+}
+
+/// Custom emoji only say whether they're animated in the raw `<a:name:id>`
+/// markup, so look for that before picking the CDN format.
+fn custom_emoji_url(message: &Message, emoji_id: &str) -> String {
+    let animated = Regex::new(&format!(r"<a:\w+:{}>", regex::escape(emoji_id)))
+        .is_ok_and(|animated_regex| animated_regex.is_match(&message.content));
+
+    let extension = match animated {
+        true => "gif",
+        false => "png",
+    };
+
+    format!(
+        "https://cdn.discordapp.com/emojis/{}.{}?size=64",
+        emoji_id, extension
+    )
+}
+
+/// Past this many emoji, a message prints at the usual size.
+const JUMBO_EMOJI_LIMIT: usize = 12;
+
+/// The longest sequence of characters Twemoji has a single image for, such
+/// as a family joined with zero width joiners.
+const MAX_EMOJI_CHARS: usize = 10;
+
+enum Emoji {
+    Twemoji(&'static [u8]),
+    Custom(String),
+}
+
+/// Splits `text` into Twemoji, taking the longest match each time so flags
+/// and joined emoji stay whole. `None` if there's anything else in it.
+fn split_emoji(text: &str) -> Option<Vec<Emoji>> {
+    let mut emoji = Vec::new();
+    let mut rest = text;
+
+    while let Some(c) = rest.chars().next() {
+        if c.is_whitespace() || c == '\u{fe0f}' {
+            rest = &rest[c.len_utf8()..];
+            continue;
+        }
+
+        let (png_data, length) = rest
+            .char_indices()
+            .map(|(index, c)| index + c.len_utf8())
+            .take(MAX_EMOJI_CHARS)
+            .filter_map(|end| {
+                let png_asset = PngTwemojiAsset::from_emoji(&rest[..end])?;
+                let png_data: &'static [u8] = png_asset;
+
+                Some((png_data, end))
+            })
+            .last()?;
+
+        emoji.push(Emoji::Twemoji(png_data));
+        rest = &rest[length..];
+    }
+
+    Some(emoji)
+}
+
+/// Draws a message made of nothing but emoji as one row of large emoji, the
+/// way Discord shows them.
+async fn jumbo_emoji(message: &Message) -> Option<Vec<u8>> {
+    let mut emoji = Vec::new();
+
+    for expression in parse(&message.content) {
+        match expression {
+            Expression::Text(text) => emoji.extend(split_emoji(text)?),
+            Expression::CustomEmoji(_, emoji_id) => emoji.push(Emoji::Custom(emoji_id.to_string())),
+            Expression::Newline => {}
+            _ => return None,
+        }
+    }
+
+    if emoji.is_empty() || emoji.len() > JUMBO_EMOJI_LIMIT {
+        return None;
+    }
+
+    let limits = CONFIG.limits(JobSource::Discord).clone();
+    let mut downloads = Vec::new();
+
+    for emoji in emoji {
+        downloads.push(match emoji {
+            Emoji::Twemoji(png_data) => png_data.to_vec(),
+            Emoji::Custom(emoji_id) => {
+                assets::fetch(&custom_emoji_url(message, &emoji_id), &limits)
+                    .await
+                    .ok()?
+            }
+        });
+    }
+
+    tokio::task::spawn_blocking(move || {
+        let images = downloads
+            .iter()
+            .map(|data| imaging::decode(data, &ImageSettings::default(), &limits))
+            .collect::<Result<Vec<_>, _>>()
+            .ok()?;
+
+        imaging::emoji_row(&images).ok()
+    })
+    .await
+    .ok()?
+}
+
+/// A readable stand-in for a URL: its host and last path segment.
+fn short_url(url: &str) -> String {
+    let Ok(parsed_url) = Url::parse(url) else {
+        return url.to_string();
+    };
+
+    let host = parsed_url.host_str().unwrap_or_default();
+    let file_name = parsed_url
+        .path_segments()
+        .and_then(|mut segments| segments.next_back())
+        .unwrap_or_default();
+
+    match parsed_url.path().trim_matches('/') == file_name {
+        true => format!("{}/{}", host, file_name),
+        false => format!("{}/.../{}", host, file_name),
+    }
+}
+
+/// The line-level constructs `discord_markdown` doesn't know about. `Markdown`
+/// is everything else, still with its newlines. `Quote` starts a `>>>` quote,
+/// which runs to the end of the message.
+#[derive(Debug, PartialEq)]
+enum Block<'a> {
+    Markdown(String),
+    Quote,
+    Heading(usize, &'a str),
+    ListItem {
+        depth: usize,
+        marker: &'a str,
+        text: &'a str,
+    },
+    Subtext(&'a str),
+}
+
+fn split_blocks(content: &str) -> Vec<Block<'_>> {
+    let (Ok(heading_regex), Ok(list_regex), Ok(subtext_regex)) = (
+        Regex::new(r"^(#{1,3}) +(.*)$"),
+        Regex::new(r"^( *)([-*]|\d+\.) +(.*)$"),
+        Regex::new(r"^-# +(.*)$"),
+    ) else {
+        return vec![Block::Markdown(content.to_string())];
+    };
+
+    let mut blocks = Vec::new();
+    let mut markdown = String::new();
+    let mut in_code_block = false;
+    let mut in_quote = false;
+
+    for line in content.split_inclusive('\n') {
+        // The quote is drawn around the blocks after it, so the ones inside
+        // still get picked out.
+        let line = match line.strip_prefix(">>> ") {
+            Some(rest) if !in_code_block && !in_quote => {
+                if !markdown.is_empty() {
+                    blocks.push(Block::Markdown(markdown));
+                    markdown = String::new();
+                }
+
+                blocks.push(Block::Quote);
+                in_quote = true;
+                rest
+            }
+            _ => line,
+        };
+
+        let trimmed_line = line.trim_end_matches(['\r', '\n']);
+
+        let block = match in_code_block {
+            true => None,
+            false => {
+                if let Some(captures) = subtext_regex.captures(trimmed_line) {
+                    captures.get(1).map(|text| Block::Subtext(text.as_str()))
+                } else if let Some(captures) = heading_regex.captures(trimmed_line) {
+                    match (captures.get(1), captures.get(2)) {
+                        (Some(level), Some(text)) => {
+                            Some(Block::Heading(level.len(), text.as_str()))
+                        }
+                        _ => None,
+                    }
+                } else if let Some(captures) = list_regex.captures(trimmed_line) {
+                    match (captures.get(1), captures.get(2), captures.get(3)) {
+                        (Some(indent), Some(marker), Some(text)) => Some(Block::ListItem {
+                            depth: indent.len() / 2,
+                            marker: marker.as_str(),
+                            text: text.as_str(),
+                        }),
+                        _ => None,
+                    }
+                } else {
+                    None
+                }
+            }
+        };
+
+        if line.matches("```").count() % 2 == 1 {
+            in_code_block = !in_code_block;
+        }
+
+        let Some(block) = block else {
+            markdown.push_str(line);
+            continue;
+        };
+
+        if !markdown.is_empty() {
+            blocks.push(Block::Markdown(markdown));
+            markdown = String::new();
+        }
+
+        blocks.push(block);
+    }
+
+    if !markdown.is_empty() {
+        blocks.push(Block::Markdown(markdown));
+    }
+
+    blocks
+}
+
+/// The native code page has no bullet characters.
+fn bullet(text_mode: TextMode, depth: usize) -> &'static str {
+    match (text_mode, depth) {
+        (TextMode::Native, 0) => "-",
+        (TextMode::Native, _) => "*",
+        (TextMode::Raster, 0) => "•",
+        (TextMode::Raster, _) => "◦",
+    }
+}
+
+/// Likewise for box-drawing characters.
+fn quote_gutter(text_mode: TextMode) -> &'static str {
+    match text_mode {
+        TextMode::Native => "| ",
+        TextMode::Raster => "│ ",
+    }
+}
+
+async fn render_content(
+    printer_commands: &mut PrinterMessage,
+    footer: &mut Footer,
+    context: &Context,
+    message: &Message,
+    content: &str,
+) {
+    let text_mode = CONFIG.channel(message.channel_id).text_mode;
+    let mut in_quote = false;
+
+    for block in split_blocks(content) {
+        match block {
+            Block::Markdown(text) => {
+                render_vec_expr(printer_commands, footer, context, message, &parse(&text)).await;
+            }
+            Block::Quote => {
+                let gutter = quote_gutter(text_mode);
+
+                printer_commands.push(PrinterInstruction::Indent {
+                    first_line: gutter.to_owned(),
+                    hanging: gutter.to_owned(),
+                });
+                in_quote = true;
+            }
+            Block::Heading(level, text) => {
+                let (width, height) = match level {
+                    1 => (2, 2),
+                    2 => (1, 2),
+                    _ => (1, 1),
+                };
+
+                printer_commands.push(PrinterInstruction::Size { width, height });
+                printer_commands.push(PrinterInstruction::Bold(true));
+                render_vec_expr(printer_commands, footer, context, message, &parse(text)).await;
+                printer_commands.push(PrinterInstruction::Bold(false));
+                printer_commands.push(PrinterInstruction::Size {
+                    width: 1,
+                    height: 1,
+                });
+                printer_commands.push(PrinterInstruction::Text("\n".to_owned()));
+            }
+            Block::ListItem {
+                depth,
+                marker,
+                text,
+            } => {
+                let marker = match marker {
+                    "-" | "*" => bullet(text_mode, depth),
+                    marker => marker,
+                };
+                let first_line = format!("{}{} ", "  ".repeat(depth), marker);
+
+                printer_commands.push(PrinterInstruction::Indent {
+                    hanging: " ".repeat(display_width(&first_line)),
+                    first_line,
+                });
+                render_vec_expr(printer_commands, footer, context, message, &parse(text)).await;
+                printer_commands.push(PrinterInstruction::Dedent);
+            }
+            Block::Subtext(text) => {
+                printer_commands.push(PrinterInstruction::Font(Font::B));
+                render_vec_expr(printer_commands, footer, context, message, &parse(text)).await;
+                printer_commands.push(PrinterInstruction::Font(Font::A));
+                printer_commands.push(PrinterInstruction::Text("\n".to_owned()));
+            }
+        }
+    }
+
+    if in_quote {
+        printer_commands.push(PrinterInstruction::Dedent);
+    }
+}
+
+/// The word right after the opening fence names the language, as long as
+/// the code starts on the next line.
+fn split_language(code: &str) -> (Option<&str>, &str) {
+    match code.split_once('\n') {
+        Some((first_line, rest))
+            if first_line
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || "+#-_.".contains(c)) =>
+        {
+            let language = (!first_line.is_empty()).then_some(first_line);
+
+            (language, rest.strip_suffix('\n').unwrap_or(rest))
+        }
+        _ => (None, code),
+    }
+}
+
+fn push_token(printer_commands: &mut PrinterMessage, kind: TokenKind, text: String) {
+    let (start, end) = match kind {
+        TokenKind::Plain => {
+            printer_commands.push(PrinterInstruction::Text(text));
+            return;
+        }
+        TokenKind::Keyword => (
+            PrinterInstruction::Bold(true),
+            PrinterInstruction::Bold(false),
+        ),
+        TokenKind::String => (
+            PrinterInstruction::Underline(UnderlineMode::Single),
+            PrinterInstruction::Underline(UnderlineMode::None),
+        ),
+        // Comments drop out of the reversed box.
+        TokenKind::Comment => (
+            PrinterInstruction::Reverse(false),
+            PrinterInstruction::Reverse(true),
+        ),
+    };
+
+    printer_commands.push(start);
+    printer_commands.push(PrinterInstruction::Text(text));
+    printer_commands.push(end);
+}
+
+/// The columns left on a line inside the `Indent` blocks still open at the
+/// end of `printer_commands`, since `layout` puts their prefixes in front.
+fn available_width(printer_commands: &PrinterMessage) -> usize {
+    let mut prefix_widths = Vec::new();
+
+    for instruction in printer_commands {
+        match instruction {
+            PrinterInstruction::Indent {
+                first_line,
+                hanging,
+            } => prefix_widths.push(display_width(first_line).max(display_width(hanging))),
+            PrinterInstruction::Dedent => {
+                prefix_widths.pop();
+            }
+            _ => {}
+        }
+    }
+
+    usize::from(CHARS_PER_LINE).saturating_sub(prefix_widths.iter().sum())
+}
+
+/// Prints `code` as a reversed box `width` columns wide, with `label` in its
+/// top edge and tokens styled when `language` is known.
+fn render_code_block(
+    printer_commands: &mut PrinterMessage,
+    code_config: &CodeConfig,
+    width: usize,
+    label: Option<&str>,
+    language: Option<&str>,
+    code: &str,
+) {
+    let code = code.replace('\t', "    ");
+    let lines = match language.and_then(highlight::language) {
+        Some(language) if code_config.highlight => highlight::highlight(&code, language),
+        _ => code
+            .split('\n')
+            .map(|line| vec![(TokenKind::Plain, line.to_string())])
+            .collect(),
+    };
+
+    let number_width = match code_config.line_numbers {
+        true => lines.len().to_string().len() + 1,
+        false => 0,
+    };
+    let code_width = width.saturating_sub(4 + number_width).max(1);
+    let width = code_width + 4 + number_width;
+
+    let label = split_columns(label.unwrap_or_default(), code_width).swap_remove(0);
+
+    printer_commands.push(PrinterInstruction::Text("\n".to_owned()));
+    printer_commands.push(PrinterInstruction::Reverse(true));
+    printer_commands.push(PrinterInstruction::Text(format!(
+        "  {}{}",
+        label,
+        " ".repeat(width.saturating_sub(2 + display_width(&label)))
+    )));
+
+    for (line_index, tokens) in lines.into_iter().enumerate() {
+        let line: String = tokens.iter().map(|(_, text)| text.as_str()).collect();
+        let mut chars = tokens
+            .iter()
+            .flat_map(|(kind, text)| text.chars().map(move |c| (*kind, c)));
+
+        for (piece_index, piece) in split_columns(&line, code_width).into_iter().enumerate() {
+            let number = match (number_width, piece_index) {
+                (0, _) => String::new(),
+                (_, 0) => format!("{:>1$} ", line_index + 1, number_width - 1),
+                _ => " ".repeat(number_width),
+            };
+
+            printer_commands.push(PrinterInstruction::Text(format!("\n  {}", number)));
+
+            let mut segments: Vec<(TokenKind, String)> = Vec::new();
+
+            for (kind, c) in chars.by_ref().take(piece.chars().count()) {
+                match segments.last_mut() {
+                    Some((last_kind, text)) if *last_kind == kind => text.push(c),
+                    _ => segments.push((kind, c.to_string())),
+                }
+            }
+
+            for (kind, text) in segments {
+                push_token(printer_commands, kind, text);
+            }
+
+            let padding = code_width.saturating_sub(display_width(&piece));
+
+            printer_commands.push(PrinterInstruction::Text(format!(
+                "{}  ",
+                " ".repeat(padding)
+            )));
+        }
+    }
+
+    printer_commands.push(PrinterInstruction::Text(format!(
+        "\n{}\n",
+        " ".repeat(width)
+    )));
+    printer_commands.push(PrinterInstruction::Reverse(false));
+}
 
 async fn render_vec_expr(
     printer_commands: &mut PrinterMessage,
+    footer: &mut Footer,
     context: &Context,
     message: &Message,
     parsed_content: &[Expression<'_>],
 ) {
     Box::pin(async move {
         for expression in parsed_content {
-            render_expr(printer_commands, context, message, expression).await;
+            render_expr(printer_commands, footer, context, message, expression).await;
         }
     })
     .await;
@@ -23,6 +543,7 @@ async fn render_vec_expr(
 
 async fn render_expr(
     printer_commands: &mut PrinterMessage,
+    footer: &mut Footer,
     context: &Context,
     message: &Message,
     expr: &Expression<'_>,
@@ -38,9 +559,10 @@ async fn render_expr(
             //}
             printer_commands.push(PrinterInstruction::Text(((*text).to_owned()).to_owned()));
         }
-        Expression::CustomEmoji(_, emoji2) => {
+        Expression::CustomEmoji(emoji1, emoji2) => {
             printer_commands.push(PrinterInstruction::Image(
-                format!("https://cdn.discordapp.com/emojis/{}?size=64", emoji2).to_owned(),
+                Image::from(custom_emoji_url(message, emoji2))
+                    .with_fallback(format!(":{}:", emoji1)),
             ));
         }
         //Expression::CustomEmoji(_, emoji2) => {
@@ -131,6 +653,15 @@ async fn render_expr(
 
             printer_commands.push(PrinterInstruction::Reverse(false));
         }
+        Expression::Hyperlink(label, url) if label != url => {
+            printer_commands.push(PrinterInstruction::Underline(UnderlineMode::Single));
+            render_vec_expr(printer_commands, footer, context, message, &parse(label)).await;
+            printer_commands.push(PrinterInstruction::Underline(UnderlineMode::None));
+
+            let footnote = footer.footnote(url);
+
+            printer_commands.push(PrinterInstruction::Text(format!("[{}]", footnote)));
+        }
         Expression::Hyperlink(link1, _) => {
             let Some(_caps) = Regex::new(r"\.(jpg|jpeg|png|webp|gif)")
                 .unwrap()
@@ -139,82 +670,100 @@ async fn render_expr(
                 printer_commands.push(PrinterInstruction::Underline(UnderlineMode::Single));
                 printer_commands.push(PrinterInstruction::Text(link1.to_string()));
                 printer_commands.push(PrinterInstruction::Underline(UnderlineMode::None));
+                footer.links.push(link1.to_string());
                 return;
             };
 
-            printer_commands.push(PrinterInstruction::Image(link1.to_string().to_owned()));
+            printer_commands.push(PrinterInstruction::Image(
+                Image::from(link1.to_string().to_owned()).with_fallback(short_url(link1)),
+            ));
         }
         Expression::MultilineCode(code) => {
-            printer_commands.push(PrinterInstruction::Reverse(true));
-            let mut text_elements: Vec<String> = Vec::new();
+            let (language, code) = split_language(code);
+            let width = available_width(printer_commands);
 
-            text_elements.push(format!("\n{}", " ".repeat(CHARS_PER_LINE.into())));
-
-            let mut character_index = 0;
-
-            for line in code.split("\n") {
-                text_elements.push("\n  ".to_string());
+            render_code_block(
+                printer_commands,
+                &CONFIG.channel(message.channel_id).code,
+                width,
+                language,
+                language,
+                code,
+            );
+        }
+        Expression::InlineCode(code) => {
+            printer_commands.push(PrinterInstruction::Reverse(true));
+            printer_commands.push(PrinterInstruction::Text((*code).to_owned()));
+            printer_commands.push(PrinterInstruction::Reverse(false));
+        }
+        Expression::Blockquote(vec) => {
+            let gutter = quote_gutter(CONFIG.channel(message.channel_id).text_mode);
 
-                let line_length = line.len();
-                let mut characters_printed = 0;
+            printer_commands.push(PrinterInstruction::Indent {
+                first_line: gutter.to_owned(),
+                hanging: gutter.to_owned(),
+            });
+            render_vec_expr(printer_commands, footer, context, message, vec).await;
+            printer_commands.push(PrinterInstruction::Dedent);
+        }
+        // A spoiler inside a spoiler is already hidden along with it.
+        Expression::Spoiler(vec) if footer.inside_spoiler => {
+            render_vec_expr(printer_commands, footer, context, message, vec).await;
+        }
+        Expression::Spoiler(vec) => {
+            let channel_config = CONFIG.channel(message.channel_id);
+            let mut spoiler_footer = Footer::spoiler();
 
-                for char in line.chars() {
-                    text_elements.push(format!("{}", char));
-                    character_index += 1;
-                    characters_printed += 1;
+            match channel_config.spoilers {
+                SpoilerMode::Redact => push_spoiler_marker(printer_commands, None),
+                // Bare links are left out, since they're in the text already
+                // and a QR code can't be turned over with it.
+                SpoilerMode::UpsideDown => {
+                    let mut spoiler = PrinterMessage::new();
 
-                    if character_index >= CHARS_PER_LINE - 4 && characters_printed < line_length {
-                        character_index = 0;
+                    render_vec_expr(&mut spoiler, &mut spoiler_footer, context, message, vec).await;
+                    push_footnotes(&mut spoiler, None, &spoiler_footer.footnotes);
+                    turn_images_upside_down(&mut spoiler);
 
-                        text_elements.push("  \n".to_string());
-                        text_elements.push("  ".to_string());
-                    }
+                    printer_commands.push(PrinterInstruction::UpsideDown(true));
+                    printer_commands.extend(spoiler);
+                    printer_commands.push(PrinterInstruction::UpsideDown(false));
                 }
+                SpoilerMode::Fold => {
+                    let qr_config = &channel_config.qr_codes;
+                    let mut spoiler = footer.next_spoiler();
 
-                text_elements.push("  ".to_string());
-            }
+                    render_vec_expr(&mut spoiler, &mut spoiler_footer, context, message, vec).await;
+                    push_footnotes(&mut spoiler, Some(qr_config), &spoiler_footer.footnotes);
 
-            text_elements.push(" ".repeat((CHARS_PER_LINE - character_index - 4).into()));
-
-            text_elements.push(format!("\n{}\n", " ".repeat(CHARS_PER_LINE.into())));
+                    if qr_config.links {
+                        push_qr_links(&mut spoiler, qr_config, spoiler_footer.links);
+                    }
 
-            printer_commands.push(PrinterInstruction::Text(text_elements.join("")));
+                    let number = footer.fold(spoiler);
 
-            printer_commands.push(PrinterInstruction::Reverse(false));
-        }
-        Expression::InlineCode(code) => {
-            printer_commands.push(PrinterInstruction::Reverse(true));
-            printer_commands.push(PrinterInstruction::Text((*code).to_owned()));
-            printer_commands.push(PrinterInstruction::Reverse(false));
-        }
-        Expression::Blockquote(vec) => {
-            printer_commands.push(PrinterInstruction::Text("\"".to_owned()));
-            render_vec_expr(printer_commands, context, message, vec).await;
-            printer_commands.push(PrinterInstruction::Text("\"\n".to_owned()));
-        }
-        Expression::Spoiler(_) => {
-            printer_commands.push(PrinterInstruction::Reverse(true));
-            printer_commands.push(PrinterInstruction::Text(" SPOILER ".to_owned()));
-            printer_commands.push(PrinterInstruction::Reverse(false));
+                    push_spoiler_marker(printer_commands, Some(number));
+                }
+            }
         }
         Expression::Underline(vec) => {
             printer_commands.push(PrinterInstruction::Underline(UnderlineMode::Single));
-            render_vec_expr(printer_commands, context, message, vec).await;
+            render_vec_expr(printer_commands, footer, context, message, vec).await;
             printer_commands.push(PrinterInstruction::Underline(UnderlineMode::None));
         }
         Expression::Strikethrough(vec) => {
             printer_commands.push(PrinterInstruction::Strike(true));
-            render_vec_expr(printer_commands, context, message, vec).await;
+            render_vec_expr(printer_commands, footer, context, message, vec).await;
             printer_commands.push(PrinterInstruction::Strike(false));
         }
         Expression::Bold(vec) => {
             printer_commands.push(PrinterInstruction::Bold(true));
-            render_vec_expr(printer_commands, context, message, vec).await;
+            render_vec_expr(printer_commands, footer, context, message, vec).await;
             printer_commands.push(PrinterInstruction::Bold(false));
         }
         Expression::Italics(vec) => {
             printer_commands.push(PrinterInstruction::Italic(true));
-            render_vec_expr(printer_commands, context, message, vec).await;
+            render_vec_expr(printer_commands, footer, context, message, vec).await;
             printer_commands.push(PrinterInstruction::Italic(false));
         }
         Expression::Newline => {
@@ -223,25 +772,371 @@ async fn render_expr(
     };
 }
 
-pub async fn print_message(
-    printer: &mpsc::Sender<PrinterMessage>,
-    context: Context,
-    message: Message,
+/// The reversed marker left where a spoiler was, numbered when it's folded
+/// away to the end of the receipt.
+fn push_spoiler_marker(printer_commands: &mut PrinterMessage, number: Option<usize>) {
+    let marker = match number {
+        Some(number) => format!(" SPOILER {} ", number),
+        None => " SPOILER ".to_owned(),
+    };
+
+    printer_commands.push(PrinterInstruction::Reverse(true));
+    printer_commands.push(PrinterInstruction::Text(marker));
+    printer_commands.push(PrinterInstruction::Reverse(false));
+}
+
+/// Turns every image in an upside down span over along with its text.
+fn turn_images_upside_down(printer_commands: &mut PrinterMessage) {
+    for instruction in printer_commands.iter_mut() {
+        if let PrinterInstruction::Image(image) = instruction {
+            image.settings.upside_down = Some(true);
+        }
+    }
+}
+
+/// Lists masked link targets under their `[n]` markers, as QR codes when
+/// `qr_config` asks for them. Upside down text passes `None`, since QR codes
+/// don't turn with it.
+fn push_footnotes(
+    printer_commands: &mut PrinterMessage,
+    qr_config: Option<&QrCodeConfig>,
+    footnotes: &[String],
 ) {
-    println!("{:?}", message);
+    if !footnotes.is_empty() {
+        printer_commands.push(PrinterInstruction::Text("\n".to_owned()));
+    }
 
-    let context = &context;
-    let message = &message;
+    for (index, url) in footnotes.iter().enumerate() {
+        let marker = format!("[{}] ", index + 1);
+
+        if let Some(qr_config) = qr_config.filter(|qr_config| qr_config.links) {
+            push_qr_code(
+                printer_commands,
+                qr_config,
+                url,
+                format!("{}{}", marker, short_url(url)),
+            );
+            continue;
+        }
 
-    let author_name = if let Some(guild_id) = message.guild_id {
+        printer_commands.push(PrinterInstruction::Indent {
+            hanging: " ".repeat(marker.len()),
+            first_line: marker,
+        });
+        printer_commands.push(PrinterInstruction::Text(url.clone()));
+        printer_commands.push(PrinterInstruction::Dedent);
+    }
+}
+
+/// Prints a QR code for each link, once each.
+fn push_qr_links(
+    printer_commands: &mut PrinterMessage,
+    qr_config: &QrCodeConfig,
+    links: Vec<String>,
+) {
+    let mut printed_links = Vec::new();
+
+    for link in links {
+        if printed_links.contains(&link) {
+            continue;
+        }
+
+        push_qr_code(printer_commands, qr_config, &link, short_url(&link));
+
+        printed_links.push(link);
+    }
+}
+
+fn push_qr_code(
+    printer_commands: &mut PrinterMessage,
+    qr_config: &QrCodeConfig,
+    url: &str,
+    caption: String,
+) {
+    printer_commands.push(PrinterInstruction::Justify(JustifyMode::Center));
+    printer_commands.push(PrinterInstruction::QrCode(QrCode {
+        data: url.to_string(),
+        size: qr_config.size,
+        correction: qr_config.correction,
+    }));
+    printer_commands.push(PrinterInstruction::Text(format!("{}\n", caption)));
+    printer_commands.push(PrinterInstruction::Justify(JustifyMode::Left));
+}
+
+const TEXT_EXTENSIONS: [&str; 18] = [
+    "txt", "md", "log", "csv", "rs", "py", "js", "ts", "json", "sh", "toml", "yaml", "yml", "c",
+    "h", "cpp", "java", "go",
+];
+
+fn extension(filename: &str) -> Option<&str> {
+    filename.rsplit_once('.').map(|(_, extension)| extension)
+}
+
+fn is_text_attachment(attachment: &Attachment) -> bool {
+    let content_type = attachment.content_type.as_deref().unwrap_or_default();
+
+    content_type.starts_with("text/")
+        || content_type.starts_with("application/json")
+        || extension(&attachment.filename)
+            .is_some_and(|extension| TEXT_EXTENSIONS.contains(&extension.to_lowercase().as_str()))
+}
+
+/// Downloads a text attachment that's small enough to print, cut down to the
+/// configured number of lines.
+async fn text_attachment(attachment: &Attachment, config: &TextAttachmentConfig) -> Option<String> {
+    if !config.enabled
+        || !is_text_attachment(attachment)
+        || u64::from(attachment.size) > config.max_bytes
+    {
+        return None;
+    }
+
+    let limits = ImageLimits {
+        max_download_bytes: config.max_bytes,
+        ..CONFIG.limits(JobSource::Discord).clone()
+    };
+
+    let data = assets::fetch(&attachment.url, &limits).await.ok()?;
+
+    if data.contains(&0) {
+        return None;
+    }
+
+    let text = String::from_utf8_lossy(&data);
+    let text = text.trim_end();
+    let line_count = text.lines().count();
+
+    if line_count <= config.max_lines {
+        return Some(text.to_string());
+    }
+
+    let mut lines: Vec<&str> = text.lines().take(config.max_lines).collect();
+    let more = format!("... {} more lines", line_count - config.max_lines);
+
+    lines.push(&more);
+
+    Some(lines.join("\n"))
+}
+
+/// Downloads `attachments` and lays them out in a single image, which then
+/// goes through the printer's image settings like any other.
+async fn collage(attachments: &[&Attachment], config: &CollageConfig) -> Option<Vec<u8>> {
+    let limits = CONFIG.limits(JobSource::Discord).clone();
+    let mut downloads = Vec::new();
+
+    for attachment in attachments {
+        downloads.push(assets::fetch(&attachment.proxy_url, &limits).await.ok()?);
+    }
+
+    let (columns, gap) = (config.columns, config.gap);
+
+    tokio::task::spawn_blocking(move || {
+        let images = downloads
+            .iter()
+            .map(|data| imaging::decode(data, &ImageSettings::default(), &limits))
+            .collect::<Result<Vec<_>, _>>()
+            .ok()?;
+
+        imaging::collage(&images, columns, gap, &limits).ok()
+    })
+    .await
+    .ok()?
+}
+
+/// A voice message's length, in place of the file name.
+fn voice_message(attachment: &Attachment) -> PrinterMessage {
+    let seconds = attachment.duration_secs.unwrap_or_default().round() as u64;
+    let label = format!("Voice message, {}:{:02}", seconds / 60, seconds % 60);
+
+    vec![
+        PrinterInstruction::Underline(UnderlineMode::Single),
+        PrinterInstruction::Text(format!("\n\n{}", label)),
+        PrinterInstruction::Underline(UnderlineMode::None),
+    ]
+}
+
+/// A voice message's waveform, if it can be drawn.
+fn waveform_image(waveform: &[u8]) -> PrinterMessage {
+    match imaging::waveform(waveform) {
+        Ok(png_data) => vec![PrinterInstruction::Image(Image::from(ImageSource::Data(
+            png_data,
+        )))],
+        Err(_) => vec![],
+    }
+}
+
+/// Prints a text attachment as a code block labelled with its file name,
+/// as wide as the indents around it allow.
+fn push_text_attachment(
+    printer_commands: &mut PrinterMessage,
+    code_config: &CodeConfig,
+    attachment: &Attachment,
+    text: &str,
+) {
+    let width = available_width(printer_commands);
+
+    render_code_block(
+        printer_commands,
+        code_config,
+        width,
+        Some(&attachment.filename),
+        extension(&attachment.filename),
+        text,
+    );
+}
+
+/// An image attachment with its alt text as a caption underneath. Turned
+/// upside down, the caption has to be printed first to end up below it.
+fn attachment_image(attachment: &Attachment, upside_down: bool) -> PrinterMessage {
+    let image = PrinterInstruction::Image(
+        Image::from(attachment.proxy_url.clone())
+            .with_settings(ImageOverrides {
+                upside_down: upside_down.then_some(true),
+                ..ImageOverrides::default()
+            })
+            .with_fallback(format!("\n[{}]", attachment.filename)),
+    );
+
+    let Some(description) = &attachment.description else {
+        return vec![image];
+    };
+
+    let mut caption = vec![
+        PrinterInstruction::Justify(JustifyMode::Center),
+        PrinterInstruction::Italic(true),
+        PrinterInstruction::Text(description.clone()),
+        PrinterInstruction::Italic(false),
+        PrinterInstruction::Justify(JustifyMode::Left),
+    ];
+
+    match upside_down {
+        true => {
+            caption.insert(0, PrinterInstruction::UpsideDown(true));
+            caption.push(PrinterInstruction::UpsideDown(false));
+            caption.push(image);
+
+            caption
+        }
+        false => {
+            caption.insert(0, image);
+
+            caption
+        }
+    }
+}
+
+async fn author_name(context: &Context, message: &Message) -> String {
+    if let Some(guild_id) = message.guild_id {
         match message.author.nick_in(context, guild_id).await {
             Some(name) => name,
             None => message.author.name.clone(),
         }
     } else {
         message.author.name.clone()
+    }
+}
+
+/// Replies and embeds only get this many lines of text, so a link preview or
+/// a long message being answered doesn't run on down the paper.
+const PREVIEW_LINES: usize = 3;
+
+/// The first few lines of `text`, with `...` where the rest was cut off.
+fn preview(text: &str) -> String {
+    let text = text.trim_end();
+    let lines: Vec<&str> = text.lines().take(PREVIEW_LINES).collect();
+    let preview: String = lines
+        .join("\n")
+        .chars()
+        .take(PREVIEW_LINES * usize::from(CHARS_PER_LINE))
+        .collect();
+
+    match preview.len() < text.len() {
+        true => format!("{}...", preview.trim_end()),
+        false => preview,
+    }
+}
+
+/// An embed's title and the start of its description in a quote, standing
+/// in for the colored bar Discord draws. Quotes inside the description nest
+/// in it.
+async fn render_embed(
+    printer_commands: &mut PrinterMessage,
+    footer: &mut Footer,
+    context: &Context,
+    message: &Message,
+    embed: &Embed,
+) {
+    if embed.title.is_none() && embed.description.is_none() {
+        return;
+    }
+
+    let gutter = quote_gutter(CONFIG.channel(message.channel_id).text_mode);
+
+    printer_commands.push(PrinterInstruction::Indent {
+        first_line: gutter.to_owned(),
+        hanging: gutter.to_owned(),
+    });
+
+    if let Some(title) = &embed.title {
+        printer_commands.push(PrinterInstruction::Bold(true));
+        printer_commands.push(PrinterInstruction::Text(format!("{}\n", title)));
+        printer_commands.push(PrinterInstruction::Bold(false));
+    }
+
+    if let Some(description) = &embed.description {
+        render_content(
+            printer_commands,
+            footer,
+            context,
+            message,
+            &preview(description),
+        )
+        .await;
+        printer_commands.push(PrinterInstruction::Text("\n".to_owned()));
+    }
+
+    printer_commands.push(PrinterInstruction::Dedent);
+}
+
+/// Avatar URLs ask the CDN for 1024 pixels, far more than the few dozen dots
+/// the avatar prints at.
+fn small_avatar(url: String) -> String {
+    let Ok(mut small) = Url::parse(&url) else {
+        return url;
+    };
+
+    small.query_pairs_mut().clear().append_pair("size", "128");
+
+    small.into()
+}
+
+/// The author's server avatar, or else their own, or else the default one
+/// Discord gives everyone without an avatar. The default one also stands in
+/// when the others can't be fetched.
+async fn author_avatar(context: &Context, message: &Message) -> ImageSource {
+    let url = match message.member(context).await {
+        Ok(member) => member.face(),
+        Err(_) => message.author.face(),
     };
 
+    match assets::fetch(&small_avatar(url), CONFIG.limits(JobSource::Discord)).await {
+        Ok(data) => ImageSource::Data(data),
+        Err(_) => ImageSource::Url(message.author.default_avatar_url()),
+    }
+}
+
+pub async fn print_message(
+    printer: &UnboundedSender<PrintJob>,
+    context: Context,
+    message: Message,
+) {
+    println!("{:?}", message);
+
+    let context = &context;
+    let message = &message;
+
+    let author_name = author_name(context, message).await;
+
     let channel_name = if let Ok(channel) = message.channel(context).await {
         if let Some(channel) = channel.guild() {
             format!("#{}", channel.name).to_string()
@@ -252,8 +1147,25 @@ pub async fn print_message(
         "Direct Messages".to_string()
     };
 
+    let channel_config = CONFIG.channel(message.channel_id);
+
     let mut printer_commands = PrinterMessage::new();
 
+    printer_commands.push(PrinterInstruction::TextMode(channel_config.text_mode));
+    printer_commands.push(PrinterInstruction::ImageSettings(channel_config.image));
+
+    if channel_config.avatars.enabled {
+        let settings = ImageOverrides {
+            width: Some(channel_config.avatars.width),
+            animation: Some(AnimationMode::First),
+            ..ImageOverrides::default()
+        };
+
+        printer_commands.push(PrinterInstruction::Image(
+            Image::from(author_avatar(context, message).await).with_settings(settings),
+        ));
+    }
+
     printer_commands.push(PrinterInstruction::Reverse(true));
     printer_commands.push(PrinterInstruction::Text(
         format!("@{}", author_name).to_owned(),
@@ -268,47 +1180,414 @@ pub async fn print_message(
 
     printer_commands.push(PrinterInstruction::Text("\n\n".to_owned()));
 
-    render_vec_expr(
-        &mut printer_commands,
-        context,
-        message,
-        &parse(&message.content),
-    )
-    .await;
+    let mut footer = Footer::default();
+
+    // Replies quote the start of the message they answer, so quotes inside
+    // it nest.
+    if let Some(referenced_message) = &message.referenced_message {
+        let gutter = quote_gutter(channel_config.text_mode);
+
+        printer_commands.push(PrinterInstruction::Indent {
+            first_line: gutter.to_owned(),
+            hanging: gutter.to_owned(),
+        });
+        printer_commands.push(PrinterInstruction::Reverse(true));
+        printer_commands.push(PrinterInstruction::Text(format!(
+            "@{}",
+            author_name(context, referenced_message).await
+        )));
+        printer_commands.push(PrinterInstruction::Reverse(false));
+        printer_commands.push(PrinterInstruction::Text("\n".to_owned()));
+        render_content(
+            &mut printer_commands,
+            &mut footer,
+            context,
+            referenced_message,
+            &preview(&referenced_message.content),
+        )
+        .await;
+        printer_commands.push(PrinterInstruction::Dedent);
+        printer_commands.push(PrinterInstruction::Text("\n".to_owned()));
+    }
+
+    match jumbo_emoji(message).await {
+        Some(png_data) => printer_commands.push(PrinterInstruction::Image(
+            Image::from(ImageSource::Data(png_data)).with_fallback(message.content.clone()),
+        )),
+        None => {
+            render_content(
+                &mut printer_commands,
+                &mut footer,
+                context,
+                message,
+                &message.content,
+            )
+            .await
+        }
+    }
 
     let attachments = &message.attachments;
     let stickers = &message.sticker_items;
 
     for sticker in stickers {
-        if let Some(sticker_url) = &sticker.image_url() {
+        let sticker_name = format!("[{}]", sticker.name);
+
+        // Lottie stickers are JSON animations, which the imaging stage draws
+        // itself.
+        let animated = match sticker.format_type {
+            StickerFormatType::Png => false,
+            StickerFormatType::Apng | StickerFormatType::Gif | StickerFormatType::Lottie => true,
+            _ => {
+                printer_commands.push(PrinterInstruction::Text(sticker_name));
+                continue;
+            }
+        };
+
+        let Some(sticker_url) = sticker.image_url() else {
+            printer_commands.push(PrinterInstruction::Text(sticker_name));
+            continue;
+        };
+
+        let settings = ImageOverrides {
+            animation: animated.then_some(channel_config.stickers.animation),
+            ..ImageOverrides::default()
+        };
+
+        printer_commands.push(PrinterInstruction::Image(
+            Image::from(sticker_url)
+                .with_settings(settings)
+                .with_fallback(sticker_name),
+        ));
+    }
+
+    // Tenor and Giphy links embed as "gifv", whose thumbnail is the only
+    // image Discord gives us for them.
+    for embed in &message.embeds {
+        if embed.kind.as_deref() != Some("gifv") {
+            render_embed(&mut printer_commands, &mut footer, context, message, embed).await;
+            continue;
+        }
+
+        if let Some(thumbnail) = &embed.thumbnail {
+            let link = embed.url.as_deref().unwrap_or(&thumbnail.url);
+
             printer_commands.push(PrinterInstruction::Image(
-                sticker_url.to_owned(),
+                Image::from(thumbnail.url.clone()).with_fallback(short_url(link)),
             ));
         }
     }
 
+    let qr_config = &channel_config.qr_codes;
+    let mut qr_links = Vec::new();
+
+    if qr_config.links {
+        qr_links.append(&mut footer.links);
+    }
+
     if let Ok(file_regex) = Regex::new(r"image/") {
+        let collage_attachments: Vec<&Attachment> = attachments
+            .iter()
+            .filter(|attachment| {
+                attachment
+                    .content_type
+                    .as_deref()
+                    .is_some_and(|attachment_type| file_regex.is_match(attachment_type))
+                    && !attachment.filename.starts_with("SPOILER_")
+            })
+            .collect();
+
+        let collage_data = match channel_config.collage.enabled && collage_attachments.len() > 1 {
+            true => collage(&collage_attachments, &channel_config.collage).await,
+            false => None,
+        };
+
         for attachment in attachments {
-            if let Some(attachment_type) = &attachment.content_type {
-                let Some(_) = file_regex.captures(attachment_type)
-                else {
-                    printer_commands.push(PrinterInstruction::Underline(UnderlineMode::Single));
-                    printer_commands.push(PrinterInstruction::Text(format!("\n\nFile: {}", attachment.filename.clone())));
-                    printer_commands.push(PrinterInstruction::Underline(UnderlineMode::None));
+            let in_collage = collage_attachments
+                .iter()
+                .any(|member| member.id == attachment.id);
+
+            if let Some(collage_data) = collage_data.as_ref().filter(|_| in_collage) {
+                // The whole collage prints where its first image would have.
+                if collage_attachments[0].id != attachment.id {
                     continue;
-                };
-    
+                }
+
+                let filenames: Vec<&str> = collage_attachments
+                    .iter()
+                    .map(|member| member.filename.as_str())
+                    .collect();
+
                 printer_commands.push(PrinterInstruction::Underline(UnderlineMode::Single));
-                printer_commands.push(PrinterInstruction::Text(format!("\n\nFile: {}", attachment.filename.clone())));
+                printer_commands.push(PrinterInstruction::Text(format!(
+                    "\n\nFiles: {}",
+                    filenames.join(", ")
+                )));
                 printer_commands.push(PrinterInstruction::Underline(UnderlineMode::None));
-                printer_commands.push(PrinterInstruction::Image(
-                    attachment.proxy_url.to_string().to_owned(),
-                ));
+                printer_commands.push(PrinterInstruction::Image(Image::from(ImageSource::Data(
+                    collage_data.clone(),
+                ))));
+
+                for (index, member) in collage_attachments.iter().enumerate() {
+                    let Some(description) = &member.description else {
+                        continue;
+                    };
+
+                    printer_commands.push(PrinterInstruction::Italic(true));
+                    printer_commands.push(PrinterInstruction::Text(format!(
+                        "\n{}. {}",
+                        index + 1,
+                        description
+                    )));
+                    printer_commands.push(PrinterInstruction::Italic(false));
+                }
+
+                continue;
+            }
+
+            let spoiler_mode = attachment
+                .filename
+                .starts_with("SPOILER_")
+                .then_some(channel_config.spoilers);
+
+            if let Some(waveform) = &attachment.waveform {
+                let mut waveform = waveform_image(waveform);
+
+                printer_commands.extend(voice_message(attachment));
+
+                match spoiler_mode {
+                    None => printer_commands.extend(waveform),
+                    Some(SpoilerMode::Redact) => {
+                        printer_commands.push(PrinterInstruction::Text("\n".to_owned()));
+                        push_spoiler_marker(&mut printer_commands, None);
+                    }
+                    Some(SpoilerMode::UpsideDown) => {
+                        turn_images_upside_down(&mut waveform);
+                        printer_commands.extend(waveform);
+                    }
+                    Some(SpoilerMode::Fold) => {
+                        let mut spoiler = footer.next_spoiler();
+                        spoiler.extend(waveform);
+                        let number = footer.fold(spoiler);
+
+                        printer_commands.push(PrinterInstruction::Text("\n".to_owned()));
+                        push_spoiler_marker(&mut printer_commands, Some(number));
+                    }
+                }
+
+                if qr_config.attachments {
+                    qr_links.push(attachment.url.clone());
+                }
+                continue;
+            }
+            let is_image = attachment
+                .content_type
+                .as_deref()
+                .is_some_and(|attachment_type| file_regex.is_match(attachment_type));
+
+            // Text files often come without a content type, so only skip
+            // those whose name doesn't give them away either.
+            if attachment.content_type.is_none() && !is_text_attachment(attachment) {
+                continue;
+            }
+
+            printer_commands.push(PrinterInstruction::Underline(UnderlineMode::Single));
+            printer_commands.push(PrinterInstruction::Text(format!(
+                "\n\nFile: {}",
+                attachment.filename.clone()
+            )));
+            printer_commands.push(PrinterInstruction::Underline(UnderlineMode::None));
+
+            if !is_image {
+                let text = match spoiler_mode {
+                    Some(SpoilerMode::Redact) => None,
+                    _ => text_attachment(attachment, &channel_config.text_attachments).await,
+                };
+
+                match (spoiler_mode, text) {
+                    (Some(SpoilerMode::Redact), _) => {
+                        printer_commands.push(PrinterInstruction::Text("\n".to_owned()));
+                        push_spoiler_marker(&mut printer_commands, None);
+                    }
+                    (_, None) => {}
+                    (None, Some(text)) => {
+                        push_text_attachment(
+                            &mut printer_commands,
+                            &channel_config.code,
+                            attachment,
+                            &text,
+                        );
+                    }
+                    (Some(SpoilerMode::UpsideDown), Some(text)) => {
+                        printer_commands.push(PrinterInstruction::UpsideDown(true));
+                        push_text_attachment(
+                            &mut printer_commands,
+                            &channel_config.code,
+                            attachment,
+                            &text,
+                        );
+                        printer_commands.push(PrinterInstruction::UpsideDown(false));
+                    }
+                    (Some(SpoilerMode::Fold), Some(text)) => {
+                        let mut spoiler = footer.next_spoiler();
+                        push_text_attachment(&mut spoiler, &channel_config.code, attachment, &text);
+                        let number = footer.fold(spoiler);
+
+                        printer_commands.push(PrinterInstruction::Text("\n".to_owned()));
+                        push_spoiler_marker(&mut printer_commands, Some(number));
+                    }
+                }
+
+                if qr_config.attachments {
+                    qr_links.push(attachment.url.clone());
+                }
+                continue;
+            }
+
+            match spoiler_mode {
+                None => printer_commands.extend(attachment_image(attachment, false)),
+                Some(SpoilerMode::Redact) => {
+                    printer_commands.push(PrinterInstruction::Text("\n".to_owned()));
+                    push_spoiler_marker(&mut printer_commands, None);
+                }
+                Some(SpoilerMode::UpsideDown) => {
+                    printer_commands.extend(attachment_image(attachment, true));
+                }
+                Some(SpoilerMode::Fold) => {
+                    let mut spoiler = footer.next_spoiler();
+                    spoiler.extend(attachment_image(attachment, false));
+                    let number = footer.fold(spoiler);
+
+                    printer_commands.push(PrinterInstruction::Text("\n".to_owned()));
+                    push_spoiler_marker(&mut printer_commands, Some(number));
+                }
             }
         }
     }
 
+    if qr_config.jump_link {
+        qr_links.push(message.link());
+    }
+
+    push_footnotes(&mut printer_commands, Some(qr_config), &footer.footnotes);
+    push_qr_links(&mut printer_commands, qr_config, qr_links);
+
+    if !footer.spoilers.is_empty() {
+        printer_commands.push(PrinterInstruction::Text(format!(
+            "\n{:-^1$}\n",
+            " fold here ",
+            CHARS_PER_LINE.into()
+        )));
+    }
+
+    for spoiler in footer.spoilers {
+        printer_commands.extend(spoiler);
+    }
+
     printer_commands.push(PrinterInstruction::PrintCut);
 
-    let _ = printer.send(printer_commands);
+    let _ = printer.send(PrintJob {
+        source: JobSource::Discord,
+        instructions: printer_commands,
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn preview_keeps_the_first_lines() {
+        assert_eq!(preview("one\ntwo\n"), "one\ntwo");
+        assert_eq!(preview("one\ntwo\nthree\nfour"), "one\ntwo\nthree...");
+        assert_eq!(
+            preview(&"word ".repeat(100)),
+            format!("{}...", "word ".repeat(29).trim_end())
+        );
+    }
+
+    #[test]
+    fn fence_names_its_language() {
+        assert_eq!(
+            split_language("rust\nfn main() {}\n"),
+            (Some("rust"), "fn main() {}")
+        );
+        assert_eq!(split_language("c++\nint x;"), (Some("c++"), "int x;"));
+    }
+
+    #[test]
+    fn fence_without_a_language() {
+        assert_eq!(split_language("\ncode\n"), (None, "code"));
+        assert_eq!(split_language("let x = 1;\nx"), (None, "let x = 1;\nx"));
+        assert_eq!(split_language("one line"), (None, "one line"));
+    }
+
+    fn item<'a>(depth: usize, marker: &'a str, text: &'a str) -> Block<'a> {
+        Block::ListItem {
+            depth,
+            marker,
+            text,
+        }
+    }
+
+    #[test]
+    fn headings_go_three_levels_deep() {
+        assert_eq!(
+            split_blocks("# one\n## two\n### three\n#### four\n"),
+            [
+                Block::Heading(1, "one"),
+                Block::Heading(2, "two"),
+                Block::Heading(3, "three"),
+                Block::Markdown("#### four\n".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn lists_nest_by_indent() {
+        assert_eq!(
+            split_blocks("- a\n  - b\n1. c\n   2. d"),
+            [
+                item(0, "-", "a"),
+                item(1, "-", "b"),
+                item(0, "1.", "c"),
+                item(1, "2.", "d")
+            ]
+        );
+    }
+
+    #[test]
+    fn subtext_is_picked_out() {
+        assert_eq!(
+            split_blocks("-# small\ntext\n"),
+            [
+                Block::Subtext("small"),
+                Block::Markdown("text\n".to_string())
+            ]
+        );
+    }
+
+    #[test]
+    fn code_blocks_are_left_alone() {
+        assert_eq!(
+            split_blocks("```\n# not\n- no\n```\n# yes"),
+            [
+                Block::Markdown("```\n# not\n- no\n```\n".to_string()),
+                Block::Heading(1, "yes"),
+            ]
+        );
+    }
+
+    #[test]
+    fn blocks_after_a_quote_stay_in_it() {
+        assert_eq!(
+            split_blocks("before\n>>> # title\n- item\nafter\n>>> again"),
+            [
+                Block::Markdown("before\n".to_string()),
+                Block::Quote,
+                Block::Heading(1, "title"),
+                item(0, "-", "item"),
+                Block::Markdown("after\n>>> again".to_string()),
+            ]
+        );
+    }
 }
@@ -1,5 +1,9 @@
-use std::sync::mpsc::{channel, Receiver, Sender};
+use std::{
+    collections::VecDeque,
+    sync::mpsc::{channel, Receiver, Sender},
+};
 
+use sha2::{Digest, Sha256};
 use websocket::{
     client::sync::Client,
     stream::sync::{TcpStream, TlsStream},
@@ -7,11 +11,54 @@ use websocket::{
     ClientBuilder, Message, OwnedMessage,
 };
 
-use crate::PrinterMessage;
+use crate::{config::CONFIG, ImageSource, JobSource, PrinterInstruction, PrinterMessage};
+
+/// How many binary frames, and how many bytes of them, are kept waiting for
+/// a job. The oldest frames go first.
+const MAX_PENDING_IMAGES: usize = 64;
+const MAX_PENDING_BYTES: usize = 32 * 1024 * 1024;
+
+/// Swaps `Binary` image references for the frames they name. Frames are
+/// dropped once a job has used them.
+fn attach_binary_images(
+    message: &mut PrinterMessage,
+    pending_images: &mut VecDeque<(String, Vec<u8>)>,
+) {
+    let mut used_images = Vec::new();
+
+    for instruction in message.iter_mut() {
+        let PrinterInstruction::Image(image) = instruction else {
+            continue;
+        };
+
+        let ImageSource::Binary(id) = &image.source else {
+            continue;
+        };
+
+        let Some((_, data)) = pending_images
+            .iter()
+            .find(|(pending_id, _)| pending_id == id)
+        else {
+            continue;
+        };
+
+        used_images.push(id.clone());
+        image.source = ImageSource::Data(data.clone());
+    }
+
+    pending_images.retain(|(id, _)| !used_images.contains(id));
+}
+
+fn pending_bytes(pending_images: &VecDeque<(String, Vec<u8>)>) -> usize {
+    pending_images.iter().map(|(_, data)| data.len()).sum()
+}
 
 pub struct APISocket {
     websocket_client: Option<Client<TlsStream<TcpStream>>>,
     incoming_sender: Sender<PrinterMessage>,
+    /// Binary frames waiting for a job to reference them, keyed by the hex
+    /// SHA-256 of their contents.
+    pending_images: VecDeque<(String, Vec<u8>)>,
 }
 
 impl APISocket {
@@ -21,6 +68,7 @@ impl APISocket {
         let mut socket = APISocket {
             websocket_client: None,
             incoming_sender,
+            pending_images: VecDeque::new(),
         };
 
         socket.connect();
@@ -65,15 +113,36 @@ impl APISocket {
                 'message_iterate: for message in (*websocket_client).incoming_messages().flatten() {
                     match message {
                         OwnedMessage::Text(data) => {
-                            let Ok(message): Result<PrinterMessage, serde_json::Error> =
-                                serde_json::from_str(&data)
-                            else {
-                                continue;
+                            let mut message: PrinterMessage = match serde_json::from_str(&data) {
+                                Ok(message) => message,
+                                Err(error) => {
+                                    println!("invalid message from the API: {}", error);
+                                    continue;
+                                }
                             };
 
+                            attach_binary_images(&mut message, &mut self.pending_images);
+
                             let _ = self.incoming_sender.send(message);
                         }
-                        OwnedMessage::Binary(_) => {}
+                        OwnedMessage::Binary(data) => {
+                            if data.len() as u64 > CONFIG.limits(JobSource::Api).max_download_bytes
+                            {
+                                continue;
+                            }
+
+                            while !self.pending_images.is_empty()
+                                && (self.pending_images.len() >= MAX_PENDING_IMAGES
+                                    || pending_bytes(&self.pending_images) + data.len()
+                                        > MAX_PENDING_BYTES)
+                            {
+                                self.pending_images.pop_front();
+                            }
+
+                            let id = format!("{:x}", Sha256::digest(&data));
+
+                            self.pending_images.push_back((id, data));
+                        }
                         OwnedMessage::Close(_) => {
                             let _ = websocket_client.shutdown();
                             should_shutdown = true;
@@ -89,6 +158,7 @@ impl APISocket {
 
                 if should_shutdown {
                     self.websocket_client = None;
+                    self.pending_images.clear();
                 }
             }
         }
@@ -0,0 +1,215 @@
+use crate::{PrinterInstruction, PrinterMessage, UnderlineMode};
+
+/// Nesting depth of each text attribute. `Bold(true)` opens a span and
+/// `Bold(false)` closes the innermost one, so the attribute stays on until
+/// every span that turned it on has closed.
+#[derive(Default)]
+struct StyleStack {
+    bold: usize,
+    italic: usize,
+    strike: usize,
+    reverse: usize,
+    underline: Vec<UnderlineMode>,
+}
+
+impl StyleStack {
+    fn underline(&self) -> UnderlineMode {
+        self.underline
+            .last()
+            .copied()
+            .unwrap_or(UnderlineMode::None)
+    }
+}
+
+fn nest(depth: &mut usize, enabled: bool) -> Option<bool> {
+    let was_enabled = *depth > 0;
+
+    match enabled {
+        true => *depth += 1,
+        false => *depth = depth.saturating_sub(1),
+    }
+
+    let is_enabled = *depth > 0;
+
+    (was_enabled != is_enabled).then_some(is_enabled)
+}
+
+/// Turns paired style instructions into actual style changes, dropping the
+/// ones that don't change anything and restoring the outer style when an
+/// inner span ends.
+pub fn normalize(message: PrinterMessage) -> PrinterMessage {
+    let mut styles = StyleStack::default();
+    let mut output = PrinterMessage::new();
+
+    for instruction in message {
+        match instruction {
+            PrinterInstruction::Bold(enabled) => {
+                if let Some(enabled) = nest(&mut styles.bold, enabled) {
+                    output.push(PrinterInstruction::Bold(enabled));
+                }
+            }
+            PrinterInstruction::Italic(enabled) => {
+                if let Some(enabled) = nest(&mut styles.italic, enabled) {
+                    output.push(PrinterInstruction::Italic(enabled));
+                }
+            }
+            PrinterInstruction::Strike(enabled) => {
+                if let Some(enabled) = nest(&mut styles.strike, enabled) {
+                    output.push(PrinterInstruction::Strike(enabled));
+                }
+            }
+            PrinterInstruction::Reverse(enabled) => {
+                if let Some(enabled) = nest(&mut styles.reverse, enabled) {
+                    output.push(PrinterInstruction::Reverse(enabled));
+                }
+            }
+            PrinterInstruction::Underline(mode) => {
+                let previous = styles.underline();
+
+                match mode {
+                    UnderlineMode::None => {
+                        styles.underline.pop();
+                    }
+                    mode => styles.underline.push(mode),
+                }
+
+                if styles.underline() != previous {
+                    output.push(PrinterInstruction::Underline(styles.underline()));
+                }
+            }
+            instruction => output.push(instruction),
+        }
+    }
+
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn text(text: &str) -> PrinterInstruction {
+        PrinterInstruction::Text(text.to_string())
+    }
+
+    fn assert_normalized(input: PrinterMessage, expected: PrinterMessage) {
+        assert_eq!(format!("{:?}", normalize(input)), format!("{:?}", expected));
+    }
+
+    #[test]
+    fn nested_spans_restore_outer_style() {
+        // **bold *and italic* __under **nested bold** line__**
+        let input = vec![
+            PrinterInstruction::Bold(true),
+            text("bold "),
+            PrinterInstruction::Italic(true),
+            text("and italic"),
+            PrinterInstruction::Italic(false),
+            text(" "),
+            PrinterInstruction::Underline(UnderlineMode::Single),
+            text("under "),
+            PrinterInstruction::Bold(true),
+            text("nested bold"),
+            PrinterInstruction::Bold(false),
+            text(" line"),
+            PrinterInstruction::Underline(UnderlineMode::None),
+            PrinterInstruction::Bold(false),
+        ];
+
+        assert_normalized(
+            input,
+            vec![
+                PrinterInstruction::Bold(true),
+                text("bold "),
+                PrinterInstruction::Italic(true),
+                text("and italic"),
+                PrinterInstruction::Italic(false),
+                text(" "),
+                PrinterInstruction::Underline(UnderlineMode::Single),
+                text("under "),
+                text("nested bold"),
+                text(" line"),
+                PrinterInstruction::Underline(UnderlineMode::None),
+                PrinterInstruction::Bold(false),
+            ],
+        );
+    }
+
+    #[test]
+    fn mention_keeps_code_reversed() {
+        let input = vec![
+            PrinterInstruction::Reverse(true),
+            text("ping "),
+            PrinterInstruction::Reverse(true),
+            text("@someone"),
+            PrinterInstruction::Reverse(false),
+            text(" here"),
+            PrinterInstruction::Reverse(false),
+        ];
+
+        assert_normalized(
+            input,
+            vec![
+                PrinterInstruction::Reverse(true),
+                text("ping "),
+                text("@someone"),
+                text(" here"),
+                PrinterInstruction::Reverse(false),
+            ],
+        );
+    }
+
+    #[test]
+    fn comment_drops_out_of_code_box() {
+        let input = vec![
+            PrinterInstruction::Reverse(true),
+            text("let x = 1; "),
+            PrinterInstruction::Reverse(false),
+            text("// one"),
+            PrinterInstruction::Reverse(true),
+            text("  "),
+            PrinterInstruction::Reverse(false),
+        ];
+
+        assert_normalized(
+            input,
+            vec![
+                PrinterInstruction::Reverse(true),
+                text("let x = 1; "),
+                PrinterInstruction::Reverse(false),
+                text("// one"),
+                PrinterInstruction::Reverse(true),
+                text("  "),
+                PrinterInstruction::Reverse(false),
+            ],
+        );
+    }
+
+    #[test]
+    fn unbalanced_closes_are_dropped() {
+        let input = vec![
+            PrinterInstruction::Bold(false),
+            PrinterInstruction::Underline(UnderlineMode::None),
+            text("plain"),
+            PrinterInstruction::Underline(UnderlineMode::Double),
+            PrinterInstruction::Underline(UnderlineMode::Single),
+            text("single"),
+            PrinterInstruction::Underline(UnderlineMode::None),
+            text("double"),
+            PrinterInstruction::Underline(UnderlineMode::None),
+        ];
+
+        assert_normalized(
+            input,
+            vec![
+                text("plain"),
+                PrinterInstruction::Underline(UnderlineMode::Double),
+                PrinterInstruction::Underline(UnderlineMode::Single),
+                text("single"),
+                PrinterInstruction::Underline(UnderlineMode::Double),
+                text("double"),
+                PrinterInstruction::Underline(UnderlineMode::None),
+            ],
+        );
+    }
+}